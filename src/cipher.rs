@@ -0,0 +1,98 @@
+mod age;
+pub(crate) mod gpg;
+mod openpgp;
+
+pub use self::age::Age;
+pub use gpg::Gpg;
+pub use openpgp::OpenPgp;
+
+use crate::{DearyError, Result};
+use std::fmt;
+
+/// The outcome of checking the signature of a decrypted entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verification {
+    /// The entry is not signed.
+    Unsigned,
+    /// The signature is valid. `trusted` tells whether the backend trusts the signing key.
+    Good { signer: String, trusted: bool },
+    /// The signature does not match the entry: it has been tampered with.
+    Bad { signer: String },
+    /// The signature matches, but the key has been revoked, e.g. because it was compromised, so
+    /// anyone may have made it.
+    Revoked { signer: String },
+    /// The entry is signed with a key that is not available to verify the signature.
+    UnknownKey { key_id: String },
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verification::Unsigned => write!(f, "Not signed"),
+            Verification::Good {
+                signer,
+                trusted: true,
+            } => write!(f, "Good signature from {}", signer),
+            Verification::Good {
+                signer,
+                trusted: false,
+            } => write!(f, "Good signature from untrusted key {}", signer),
+            Verification::Bad { signer } => write!(f, "BAD signature from {}", signer),
+            Verification::Revoked { signer } => {
+                write!(f, "Signature from REVOKED key {}", signer)
+            }
+            Verification::UnknownKey { key_id } => {
+                write!(f, "Signed with unknown key {}", key_id)
+            }
+        }
+    }
+}
+
+/// An encryption backend used to protect diary entries.
+///
+/// Implementations work on in-memory buffers, so that plaintext never has to touch the disk
+/// outside of the editor's temporary file.
+pub trait Cipher {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>>;
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>>;
+
+    /// Signs `plaintext` with the key `signer`, then encrypts it to `recipients`.
+    fn sign_and_encrypt(
+        &self,
+        _plaintext: &[u8],
+        _recipients: &[String],
+        _signer: &str,
+    ) -> Result<Vec<u8>> {
+        Err(DearyError::Config(
+            "Signing is not supported by this encryption backend".to_string(),
+        ))
+    }
+
+    /// Decrypts `ciphertext` and verifies its signature, if any.
+    fn decrypt_and_verify(&self, ciphertext: &[u8]) -> Result<(Vec<u8>, Verification)> {
+        Ok((self.decrypt(ciphertext)?, Verification::Unsigned))
+    }
+}
+
+impl<C: Cipher + ?Sized> Cipher for Box<C> {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        (**self).encrypt(plaintext, recipients)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        (**self).decrypt(ciphertext)
+    }
+
+    fn sign_and_encrypt(
+        &self,
+        plaintext: &[u8],
+        recipients: &[String],
+        signer: &str,
+    ) -> Result<Vec<u8>> {
+        (**self).sign_and_encrypt(plaintext, recipients, signer)
+    }
+
+    fn decrypt_and_verify(&self, ciphertext: &[u8]) -> Result<(Vec<u8>, Verification)> {
+        (**self).decrypt_and_verify(ciphertext)
+    }
+}
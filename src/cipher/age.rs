@@ -0,0 +1,123 @@
+use crate::{Cipher, DearyError, Result};
+use age::cli_common::{read_identities, read_recipients, StdinGuard};
+use age::secrecy::SecretString;
+use age::{ssh, DecryptError, Decryptor, EncryptError, Encryptor, Identity};
+use std::cell::OnceCell;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+const AGE_RECIPIENT_PREFIXES: &[&str] = &["age1", "ssh-ed25519 ", "ssh-rsa "];
+
+/// Encrypts entries with [age](https://age-encryption.org), to X25519 or SSH recipients.
+///
+/// Identities (age identity files or SSH private keys) are only read when an entry is first
+/// decrypted, so that the passphrase of a protected one is asked for once.
+pub struct Age {
+    identity_files: Vec<PathBuf>,
+    identities: OnceCell<Vec<Box<dyn Identity>>>,
+}
+
+impl From<EncryptError> for DearyError {
+    fn from(e: EncryptError) -> Self {
+        DearyError::Encryption(e.to_string())
+    }
+}
+
+impl From<DecryptError> for DearyError {
+    fn from(e: DecryptError) -> Self {
+        DearyError::Decryption(e.to_string())
+    }
+}
+
+impl Age {
+    pub fn new(identity_files: Vec<PathBuf>) -> Age {
+        Age {
+            identity_files,
+            identities: OnceCell::new(),
+        }
+    }
+
+    /// Tells whether `recipient` is an age or SSH public key, as opposed to a GPG key ID.
+    pub fn is_recipient(recipient: &str) -> bool {
+        AGE_RECIPIENT_PREFIXES
+            .iter()
+            .any(|p| recipient.trim().starts_with(p))
+    }
+
+    fn identities(&self) -> Result<&[Box<dyn Identity>]> {
+        if let Some(identities) = self.identities.get() {
+            return Ok(identities);
+        }
+        let files = self
+            .identity_files
+            .iter()
+            .filter(|f| f.exists())
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return Err(DearyError::Config(
+                "No age identity files found".to_string(),
+            ));
+        }
+
+        let mut identities = vec![];
+        let mut file_names = vec![];
+        for file in files {
+            match unlock_ssh_key(file)? {
+                Some(identity) => identities.push(identity),
+                None => file_names.push(file.to_string_lossy().into_owned()),
+            }
+        }
+        if !file_names.is_empty() {
+            identities.extend(
+                read_identities(file_names, None, &mut StdinGuard::new(false))
+                    .map_err(|e| DearyError::Decryption(e.to_string()))?,
+            );
+        }
+        Ok(self.identities.get_or_init(|| identities))
+    }
+}
+
+impl Cipher for Age {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        let recipients = read_recipients(
+            recipients.to_vec(),
+            vec![],
+            vec![],
+            None,
+            &mut StdinGuard::new(false),
+        )
+        .map_err(|e| DearyError::Encryption(e.to_string()))?;
+
+        let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as _))?;
+        let mut ciphertext = vec![];
+        let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+        writer.write_all(plaintext)?;
+        writer.finish()?;
+        Ok(ciphertext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let identities = self.identities()?;
+        let decryptor = Decryptor::new_buffered(ciphertext)?;
+        let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref() as _))?;
+
+        let mut plaintext = vec![];
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+/// Unlocks the passphrase-protected SSH key in `path`, if that is what it holds. age would
+/// otherwise ask for the passphrase again for every file it decrypts.
+fn unlock_ssh_key(path: &Path) -> Result<Option<Box<dyn Identity>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let key = match ssh::Identity::from_buffer(reader, Some(path.display().to_string())) {
+        Ok(ssh::Identity::Encrypted(key)) => key,
+        _ => return Ok(None),
+    };
+    let passphrase = rpassword::prompt_password(format!("Passphrase for {}: ", path.display()))?;
+    let key = key.decrypt(SecretString::from(passphrase))?;
+    Ok(Some(Box::new(ssh::Identity::from(key))))
+}
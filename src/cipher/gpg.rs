@@ -0,0 +1,241 @@
+use crate::{find_executable, run_with_input, Cipher, DearyError, Result, Verification};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const GPG: &str = "gpg";
+const STATUS_PREFIX: &str = "[GNUPG:] ";
+const GPG_OPTS: &[&str] = &[
+    "--quiet",
+    "--yes",
+    "--compress-algo=none",
+    "--no-encrypt-to",
+];
+
+/// Encrypts entries by running the `gpg` executable found in `PATH`.
+#[derive(Debug, Default)]
+pub struct Gpg;
+
+impl Cipher for Gpg {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        encrypt(plaintext, recipients, None)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.decrypt_and_verify(ciphertext)?.0)
+    }
+
+    fn sign_and_encrypt(
+        &self,
+        plaintext: &[u8],
+        recipients: &[String],
+        signer: &str,
+    ) -> Result<Vec<u8>> {
+        encrypt(plaintext, recipients, Some(signer))
+    }
+
+    fn decrypt_and_verify(&self, ciphertext: &[u8]) -> Result<(Vec<u8>, Verification)> {
+        let output = run_gpg(&["--status-fd=2", "--decrypt"], ciphertext)?;
+        let (status, messages) = split_status(&output.stderr);
+
+        // gpg also exits unsuccessfully on bad or unverifiable signatures, even though the
+        // entry itself has been decrypted fine
+        let decrypted = status.iter().any(|l| l.starts_with("DECRYPTION_OKAY"))
+            && !status.iter().any(|l| l.starts_with("DECRYPTION_FAILED"));
+        if output.status.success() || decrypted {
+            Ok((output.stdout, verification(&status)))
+        } else {
+            Err(DearyError::Decryption(error_details(&output, &messages)))
+        }
+    }
+}
+
+fn encrypt(plaintext: &[u8], recipients: &[String], signer: Option<&str>) -> Result<Vec<u8>> {
+    let mut args = vec![];
+    if let Some(signer) = signer {
+        args.extend(&["--sign", "--local-user", signer]);
+    }
+    args.push("--encrypt");
+    for recipient in recipients {
+        args.push("--recipient");
+        args.push(recipient);
+    }
+
+    let output = run_gpg(&args, plaintext)?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        let (_, messages) = split_status(&output.stderr);
+        Err(DearyError::Encryption(error_details(&output, &messages)))
+    }
+}
+
+/// Separates `--status-fd` lines (with their `[GNUPG:] ` prefix stripped) from the
+/// human-readable messages gpg writes to stderr.
+fn split_status(stderr: &[u8]) -> (Vec<String>, Vec<String>) {
+    let stderr = String::from_utf8_lossy(stderr);
+    let (status, messages): (Vec<&str>, Vec<&str>) =
+        stderr.lines().partition(|l| l.starts_with(STATUS_PREFIX));
+    (
+        status
+            .iter()
+            .map(|l| l[STATUS_PREFIX.len()..].to_string())
+            .collect(),
+        messages.iter().map(|l| l.to_string()).collect(),
+    )
+}
+
+fn error_details(output: &Output, messages: &[String]) -> String {
+    match messages.join("\n").trim() {
+        "" => format!("gpg {}", output.status),
+        s => s.to_string(),
+    }
+}
+
+fn verification(status: &[String]) -> Verification {
+    let trusted = status
+        .iter()
+        .any(|l| l.starts_with("TRUST_FULLY") || l.starts_with("TRUST_ULTIMATE"));
+
+    for line in status {
+        let mut fields = line.splitn(3, ' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("GOODSIG"), _, Some(user_id)) => {
+                return Verification::Good {
+                    signer: user_id.to_string(),
+                    trusted,
+                }
+            }
+            (Some("EXPKEYSIG"), _, Some(user_id)) => {
+                return Verification::Good {
+                    signer: user_id.to_string(),
+                    trusted: false,
+                }
+            }
+            (Some("REVKEYSIG"), _, Some(user_id)) => {
+                return Verification::Revoked {
+                    signer: user_id.to_string(),
+                }
+            }
+            (Some("BADSIG"), _, Some(user_id)) => {
+                return Verification::Bad {
+                    signer: user_id.to_string(),
+                }
+            }
+            (Some("ERRSIG"), Some(key_id), _) => {
+                return Verification::UnknownKey {
+                    key_id: key_id.to_string(),
+                }
+            }
+            _ => {}
+        }
+    }
+    Verification::Unsigned
+}
+
+/// Creates an ASCII-armored detached signature of `data`, the way `git commit -S` does.
+pub(crate) fn sign_detached(key: &str, data: &[u8]) -> Result<String> {
+    let output = run_gpg(
+        &[
+            "--status-fd=2",
+            "--detach-sign",
+            "--armor",
+            "--local-user",
+            key,
+        ],
+        data,
+    )?;
+    let (status, messages) = split_status(&output.stderr);
+    if output.status.success() && status.iter().any(|l| l.starts_with("SIG_CREATED")) {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(DearyError::Signing(error_details(&output, &messages)))
+    }
+}
+
+/// Verifies the detached signature in `signature_path` against `data`.
+pub(crate) fn verify_detached(signature_path: &Path, data: &[u8]) -> Result<Verification> {
+    let signature_path = signature_path.to_string_lossy();
+    let output = run_gpg(&["--status-fd=2", "--verify", &signature_path, "-"], data)?;
+    let (status, _) = split_status(&output.stderr);
+    Ok(verification(&status))
+}
+
+fn find_gpg() -> Result<PathBuf> {
+    find_executable(GPG)
+}
+
+fn run_gpg(args: &[&str], input: &[u8]) -> Result<Output> {
+    run_with_input(Command::new(find_gpg()?).args(GPG_OPTS).args(args), input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::verification;
+    use crate::Verification;
+
+    fn status(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn signatures_are_only_trusted_with_full_trust() {
+        let good = "GOODSIG 0123456789ABCDEF Alice <alice@example.com>";
+        let signer = "Alice <alice@example.com>".to_string();
+        assert_eq!(
+            verification(&status(&[good, "TRUST_ULTIMATE 0 pgp"])),
+            Verification::Good {
+                signer: signer.clone(),
+                trusted: true
+            }
+        );
+        assert_eq!(
+            verification(&status(&[good, "TRUST_UNDEFINED 0 pgp"])),
+            Verification::Good {
+                signer,
+                trusted: false
+            }
+        );
+    }
+
+    #[test]
+    fn expired_keys_are_untrusted_but_revoked_ones_are_not_good() {
+        let signer = "Alice <alice@example.com>".to_string();
+        assert_eq!(
+            verification(&status(&[
+                "EXPKEYSIG 0123456789ABCDEF Alice <alice@example.com>",
+                "TRUST_ULTIMATE 0 pgp"
+            ])),
+            Verification::Good {
+                signer: signer.clone(),
+                trusted: false
+            }
+        );
+        assert_eq!(
+            verification(&status(&[
+                "REVKEYSIG 0123456789ABCDEF Alice <alice@example.com>",
+                "TRUST_ULTIMATE 0 pgp"
+            ])),
+            Verification::Revoked { signer }
+        );
+    }
+
+    #[test]
+    fn other_signatures() {
+        assert_eq!(
+            verification(&status(&["BADSIG 0123456789ABCDEF Alice"])),
+            Verification::Bad {
+                signer: "Alice".to_string()
+            }
+        );
+        assert_eq!(
+            verification(&status(&["ERRSIG 0123456789ABCDEF 1 8 00 1700000000 9"])),
+            Verification::UnknownKey {
+                key_id: "0123456789ABCDEF".to_string()
+            }
+        );
+        assert_eq!(
+            verification(&status(&["PLAINTEXT 62 0"])),
+            Verification::Unsigned
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{Cipher, DearyError, Result, Verification};
+use pgp::composed::signed_key::{from_reader_many, PublicOrSecret};
+use pgp::crypto::hash::HashAlgorithm;
+use pgp::crypto::sym::SymmetricKeyAlgorithm;
+use pgp::ser::Serialize;
+use pgp::types::{KeyId, PublicKeyTrait};
+use pgp::{Deserializable, Message, SignedPublicKey, SignedPublicSubKey, SignedSecretKey};
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+
+const ARMOR_HEADER: &str = "-----BEGIN PGP ";
+
+/// Encrypts entries in-process with keys read from an OpenPGP keyring file, so that no `gpg`
+/// executable is needed.
+///
+/// The keyring may be binary or ASCII-armored (e.g. the output of `gpg --export` and
+/// `gpg --export-secret-keys` concatenated together). Public keys are used to encrypt, secret
+/// keys to decrypt. The passphrase of protected secret keys is asked for once, then remembered
+/// until it turns out to be wrong.
+pub struct OpenPgp {
+    public_keys: Vec<SignedPublicKey>,
+    secret_keys: Vec<SignedSecretKey>,
+    passphrase: RefCell<Option<String>>,
+}
+
+impl From<pgp::errors::Error> for DearyError {
+    fn from(e: pgp::errors::Error) -> Self {
+        DearyError::Encryption(e.to_string())
+    }
+}
+
+impl OpenPgp {
+    pub fn from_keyring(path: &Path) -> Result<OpenPgp> {
+        let data = fs::read(path)?;
+        let mut public_keys = vec![];
+        let mut secret_keys = vec![];
+
+        for block in split_armor(&data) {
+            let (keys, _) = from_reader_many(block).map_err(|e| keyring_error(path, e))?;
+            for key in keys {
+                match key.map_err(|e| keyring_error(path, e))? {
+                    PublicOrSecret::Public(k) => public_keys.push(k),
+                    PublicOrSecret::Secret(k) => {
+                        public_keys.push(SignedPublicKey::from(k.clone()));
+                        secret_keys.push(k)
+                    }
+                }
+            }
+        }
+
+        Ok(OpenPgp {
+            public_keys,
+            secret_keys,
+            passphrase: RefCell::new(None),
+        })
+    }
+
+    fn find_public_key(&self, key_id: &str) -> Result<&SignedPublicKey> {
+        match self.public_keys.iter().find(|k| matches_id(k, key_id)) {
+            Some(k) => Ok(k),
+            None => Err(DearyError::Encryption(format!(
+                "No public key for {} found in keyring",
+                key_id
+            ))),
+        }
+    }
+
+    fn is_locked(&self, key_ids: &[KeyId]) -> bool {
+        self.secret_keys.iter().any(|k| {
+            key_ids.iter().any(|id| has_key_id(k, id))
+                && (k.primary_key.secret_params().is_encrypted()
+                    || k.secret_subkeys
+                        .iter()
+                        .any(|s| s.key.secret_params().is_encrypted()))
+        })
+    }
+
+    /// The passphrase to unlock a key with, which is only asked for if it is `locked` and it
+    /// was not asked for before.
+    fn passphrase(&self, locked: bool) -> Result<String> {
+        if !locked {
+            return Ok(String::new());
+        }
+        let mut cached = self.passphrase.borrow_mut();
+        if let Some(passphrase) = cached.as_ref() {
+            return Ok(passphrase.clone());
+        }
+        let passphrase = rpassword::prompt_password("Passphrase: ")?;
+        *cached = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    /// Forgets the passphrase after a failure, in case it was the wrong one.
+    fn forget_passphrase<T, E>(
+        &self,
+        result: std::result::Result<T, E>,
+    ) -> std::result::Result<T, E> {
+        if result.is_err() {
+            self.passphrase.replace(None);
+        }
+        result
+    }
+
+    fn encrypt_message(&self, message: Message, recipients: &[String]) -> Result<Vec<u8>> {
+        let mut subkeys = vec![];
+        for recipient in recipients {
+            subkeys.push(encryption_subkey(self.find_public_key(recipient)?)?);
+        }
+        let message = message.encrypt_to_keys_seipdv1(
+            rand::thread_rng(),
+            SymmetricKeyAlgorithm::AES256,
+            &subkeys,
+        )?;
+        Ok(message.to_bytes()?)
+    }
+
+    fn decrypt_message(&self, ciphertext: &[u8]) -> Result<Message> {
+        let message = if ciphertext.starts_with(ARMOR_HEADER.as_bytes()) {
+            Message::from_armor_single(ciphertext).map(|(m, _)| m)
+        } else {
+            Message::from_bytes(ciphertext)
+        }
+        .map_err(decryption_error)?;
+
+        let passphrase = self.passphrase(self.is_locked(&recipient_key_ids(&message)))?;
+        let keys: Vec<&SignedSecretKey> = self.secret_keys.iter().collect();
+        let (decrypted, _) = self
+            .forget_passphrase(message.decrypt(|| passphrase, &keys))
+            .map_err(decryption_error)?;
+        decrypted.decompress().map_err(decryption_error)
+    }
+
+    /// Every key in the keyring is considered trusted, unless it is revoked: the keyring is
+    /// curated by its owner.
+    fn verify(&self, message: &Message) -> Verification {
+        let signature = match message {
+            Message::Signed { signature, .. } => signature,
+            _ => return Verification::Unsigned,
+        };
+        let key_id = match signature.issuer().first() {
+            Some(id) => (*id).clone(),
+            None => {
+                return Verification::UnknownKey {
+                    key_id: "(none)".to_string(),
+                }
+            }
+        };
+
+        for key in &self.public_keys {
+            let result = if key.key_id() == key_id {
+                message.verify(key)
+            } else if let Some(subkey) = key.public_subkeys.iter().find(|s| s.key_id() == key_id) {
+                message.verify(subkey)
+            } else {
+                continue;
+            };
+
+            let signer = user_id(key);
+            return match result {
+                Ok(()) if !key.details.revocation_signatures.is_empty() => {
+                    Verification::Revoked { signer }
+                }
+                Ok(()) => Verification::Good {
+                    signer,
+                    trusted: true,
+                },
+                Err(_) => Verification::Bad { signer },
+            };
+        }
+
+        Verification::UnknownKey {
+            key_id: format!("{:X}", key_id),
+        }
+    }
+}
+
+impl Cipher for OpenPgp {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        self.encrypt_message(Message::new_literal_bytes("", plaintext), recipients)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        content(&self.decrypt_message(ciphertext)?)
+    }
+
+    fn sign_and_encrypt(
+        &self,
+        plaintext: &[u8],
+        recipients: &[String],
+        signer: &str,
+    ) -> Result<Vec<u8>> {
+        let fingerprint = self.find_public_key(signer)?.fingerprint();
+        let key = match self
+            .secret_keys
+            .iter()
+            .find(|k| k.primary_key.fingerprint() == fingerprint)
+        {
+            Some(k) => k,
+            None => {
+                return Err(DearyError::Encryption(format!(
+                    "No secret key for {} found in keyring",
+                    signer
+                )))
+            }
+        };
+
+        let passphrase = self.passphrase(key.primary_key.secret_params().is_encrypted())?;
+        let message = self.forget_passphrase(Message::new_literal_bytes("", plaintext).sign(
+            rand::thread_rng(),
+            key,
+            || passphrase,
+            HashAlgorithm::SHA2_256,
+        ))?;
+        self.encrypt_message(message, recipients)
+    }
+
+    fn decrypt_and_verify(&self, ciphertext: &[u8]) -> Result<(Vec<u8>, Verification)> {
+        let message = self.decrypt_message(ciphertext)?;
+        Ok((content(&message)?, self.verify(&message)))
+    }
+}
+
+fn content(message: &Message) -> Result<Vec<u8>> {
+    match message.get_content().map_err(decryption_error)? {
+        Some(content) => Ok(content),
+        None => Err(DearyError::Decryption(
+            "Message contains no literal data".to_string(),
+        )),
+    }
+}
+
+fn user_id(key: &SignedPublicKey) -> String {
+    match key.details.users.first() {
+        Some(user) => user.id.id().to_string(),
+        None => to_hex(key.fingerprint().as_bytes()),
+    }
+}
+
+/// Splits concatenated ASCII-armored blocks, which `from_reader_many` would stop reading after
+/// the first one. Binary keyrings are returned as a single block.
+fn split_armor(data: &[u8]) -> Vec<&[u8]> {
+    if !data.starts_with(ARMOR_HEADER.as_bytes()) {
+        return vec![data];
+    }
+
+    let mut starts = vec![];
+    let mut offset = 0;
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(ARMOR_HEADER.as_bytes()) && !line.starts_with(b"-----BEGIN PGP SIG") {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts.push(data.len());
+    starts.windows(2).map(|w| &data[w[0]..w[1]]).collect()
+}
+
+fn keyring_error(path: &Path, e: pgp::errors::Error) -> DearyError {
+    DearyError::Config(format!("Cannot read keyring {}: {}", path.display(), e))
+}
+
+fn decryption_error(e: pgp::errors::Error) -> DearyError {
+    DearyError::Decryption(e.to_string())
+}
+
+fn recipient_key_ids(message: &Message) -> Vec<KeyId> {
+    match message {
+        Message::Encrypted { esk, .. } => esk
+            .iter()
+            .filter_map(|e| match e {
+                pgp::composed::message::Esk::PublicKeyEncryptedSessionKey(p) => {
+                    p.id().ok().cloned()
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn encryption_subkey(key: &SignedPublicKey) -> Result<&SignedPublicSubKey> {
+    match key
+        .public_subkeys
+        .iter()
+        .filter(|s| s.is_encryption_key())
+        .max_by_key(|s| *s.created_at())
+    {
+        Some(s) => Ok(s),
+        None => Err(DearyError::Encryption(format!(
+            "Key {} has no encryption subkey",
+            to_hex(key.fingerprint().as_bytes())
+        ))),
+    }
+}
+
+fn has_key_id(key: &SignedSecretKey, key_id: &KeyId) -> bool {
+    key.key_id() == *key_id || key.secret_subkeys.iter().any(|s| s.key_id() == *key_id)
+}
+
+/// Matches a recipient the way gpg would: by (a suffix of) a fingerprint, by key ID, or by a
+/// part of a user ID, such as an email address.
+fn matches_id(key: &SignedPublicKey, id: &str) -> bool {
+    let hex_id = id.trim_start_matches("0x").to_uppercase();
+    let fingerprints = std::iter::once(key.fingerprint())
+        .chain(key.public_subkeys.iter().map(|s| s.fingerprint()))
+        .map(|f| to_hex(f.as_bytes()));
+
+    for fingerprint in fingerprints {
+        if hex_id.len() >= 8 && fingerprint.ends_with(&hex_id) {
+            return true;
+        }
+    }
+
+    let id = id.to_lowercase();
+    key.details
+        .users
+        .iter()
+        .any(|u| u.id.id().to_string().to_lowercase().contains(&id))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
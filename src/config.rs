@@ -0,0 +1,365 @@
+//! Settings, read from `deary/config.toml` in the user's config directory, then from
+//! `.deary.toml` in the diary, which is committed along with it and overrides the former.
+//!
+//! ```toml
+//! path = "~/diary"
+//! diary = "work"
+//! tmp_dir = "/dev/shm"
+//! entry_name_format = "%Y%m%d-%H%M%S"
+//! timezone = "Europe/Amsterdam"
+//!
+//! [editor]
+//! command = "code"
+//! args = ["--wait"]
+//!
+//! [git]
+//! name = "Jane Doe"
+//! email = "jane@example.com"
+//!
+//! [diaries]
+//! work = "~/work/diary"
+//! ```
+
+use crate::signing::expand_home;
+use crate::{Change, Cipher, Deary, DearyError, Result, PRIVATE_COMMIT_MESSAGE};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, DocumentMut, Item as TomlItem, Table, Value};
+
+const USER_CONFIG_PATH: &str = "deary/config.toml";
+pub(crate) const SETTINGS_FILE_NAME: &str = ".deary.toml";
+
+/// Every setting, as the dotted path of its key.
+pub const CONFIG_KEYS: &[&str] = &[
+    "editor.command",
+    "editor.args",
+    "path",
+    "diary",
+    "tmp_dir",
+    "git.name",
+    "git.email",
+    "entry_name_format",
+    "timezone",
+];
+/// Settings that are about the user's machine rather than a diary. Those that run programs or
+/// put decrypted text somewhere are among them, so that whoever can push to a diary cannot
+/// choose them for every other clone.
+const USER_ONLY_KEYS: &[&str] = &["editor.command", "editor.args", "path", "diary", "tmp_dir"];
+const DIARIES_TABLE: &str = "diaries";
+
+/// The settings in effect. Unset ones fall back to deary's defaults.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The editor, taking precedence over `EDITOR`, and the arguments to run it with before
+    /// the file to edit.
+    pub editor: Option<String>,
+    pub editor_args: Vec<String>,
+    /// Where the default diary is, instead of `~/.deary`.
+    pub path: Option<PathBuf>,
+    /// The name of the diary to use when none is selected, see [`crate::find_repo_path`].
+    pub diary: Option<String>,
+    /// Diaries kept elsewhere than in `~/.deary.d`, by name.
+    pub diaries: BTreeMap<String, PathBuf>,
+    /// Where decrypted entries are written to while editing, instead of `/dev/shm`.
+    pub tmp_dir: Option<PathBuf>,
+    /// The identity commits are made with, instead of the repository's git config.
+    pub git_name: Option<String>,
+    pub git_email: Option<String>,
+    /// The `strftime` format new entries are named with, in UTC.
+    pub entry_name_format: Option<String>,
+    /// The timezone times are shown and dates are read in, instead of the local one.
+    pub timezone: Option<Tz>,
+}
+
+impl Config {
+    /// Reads the user's settings, then those of the diary in `repo_path`, if given.
+    pub fn load(repo_path: Option<&Path>) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(doc) = read_document(&user_config_path())? {
+            config.apply(&doc, false)?;
+        }
+        if let Some(repo_path) = repo_path {
+            if let Some(doc) = read_document(&repo_path.join(SETTINGS_FILE_NAME))? {
+                config.apply(&doc, true)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the value of setting `key`, if it is set. Lists are formatted like in TOML.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let display = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string());
+        Ok(match check_key(key)? {
+            "editor.command" => self.editor.clone(),
+            "editor.args" if self.editor_args.is_empty() => None,
+            "editor.args" => Some(
+                self.editor_args
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Array>()
+                    .to_string(),
+            ),
+            "path" => display(&self.path),
+            "diary" => self.diary.clone(),
+            "tmp_dir" => display(&self.tmp_dir),
+            "git.name" => self.git_name.clone(),
+            "git.email" => self.git_email.clone(),
+            "entry_name_format" => self.entry_name_format.clone(),
+            "timezone" => self.timezone.map(|tz| tz.name().to_string()),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Sets `key` in the user's settings. `editor.args` takes any number of values, every other
+    /// setting exactly one.
+    pub fn set_user(key: &str, values: &[&str]) -> Result<()> {
+        set_in_file(&user_config_path(), key, values, false)
+    }
+
+    /// The directory temporary files with decrypted text are written to.
+    pub(crate) fn tmp_dir(&self) -> &Path {
+        self.tmp_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(crate::TMP_DIR))
+    }
+
+    /// Overrides the settings with those in `doc`. `in_diary` tells whether they come from a
+    /// diary, which cannot have settings that are about the user's machine.
+    fn apply(&mut self, doc: &DocumentMut, in_diary: bool) -> Result<()> {
+        for key in CONFIG_KEYS {
+            if in_diary && USER_ONLY_KEYS.contains(key) {
+                continue;
+            }
+            let item = match find_item(doc, key) {
+                Some(item) => item,
+                None => continue,
+            };
+            let string = || match item.as_str() {
+                Some(s) => Ok(s.to_string()),
+                None => Err(invalid(key, "must be a string")),
+            };
+            match *key {
+                "editor.command" => self.editor = Some(string()?),
+                "editor.args" => {
+                    let args = item.as_array().and_then(|a| {
+                        a.iter()
+                            .map(|v| v.as_str().map(String::from))
+                            .collect::<Option<Vec<String>>>()
+                    });
+                    match args {
+                        Some(args) => self.editor_args = args,
+                        None => return Err(invalid(key, "must be a list of strings")),
+                    }
+                }
+                "path" => self.path = Some(expand_home(&string()?)),
+                "diary" => self.diary = Some(string()?),
+                "tmp_dir" => self.tmp_dir = Some(expand_home(&string()?)),
+                "git.name" => self.git_name = Some(string()?),
+                "git.email" => self.git_email = Some(string()?),
+                "entry_name_format" => {
+                    let format = string()?;
+                    if StrftimeItems::new(&format).any(|i| i == Item::Error) {
+                        return Err(invalid(key, "is not a valid date format"));
+                    }
+                    // Names are separated by tabs and newlines in the indexes
+                    let name = Utc::now().format(&format).to_string();
+                    if name.is_empty()
+                        || name.starts_with('.')
+                        || name.contains('/')
+                        || name.chars().any(char::is_control)
+                    {
+                        return Err(invalid(key, "does not make valid file names"));
+                    }
+                    self.entry_name_format = Some(format);
+                }
+                "timezone" => match string()?.parse::<Tz>() {
+                    Ok(tz) => self.timezone = Some(tz),
+                    Err(_) => return Err(invalid(key, "is not a known timezone")),
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        let diaries = if in_diary {
+            None
+        } else {
+            doc.get(DIARIES_TABLE)
+        };
+        if let Some(table) = diaries {
+            let table = match table.as_table_like() {
+                Some(table) => table,
+                None => return Err(invalid(DIARIES_TABLE, "must be a table")),
+            };
+            for (name, item) in table.iter() {
+                match item.as_str() {
+                    Some(path) => {
+                        self.diaries.insert(name.to_string(), expand_home(path));
+                    }
+                    None => {
+                        return Err(invalid(
+                            &format!("{}.{}", DIARIES_TABLE, name),
+                            "must be a path",
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    /// The settings of the user and of this diary, as they were when it was opened.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Sets `key` in the diary's settings, see [`Config::set_user`], and commits them.
+    pub fn set_diary_config(&self, key: &str, values: &[&str]) -> Result<()> {
+        let path = self.repo_dir().join(SETTINGS_FILE_NAME);
+        let change = if path.exists() {
+            Change::Edit
+        } else {
+            Change::Add
+        };
+        set_in_file(&path, key, values, true)?;
+        let message = if self.is_private() {
+            PRIVATE_COMMIT_MESSAGE.to_string()
+        } else {
+            format!("Set {}", key)
+        };
+        self.commit_changes(&[(SETTINGS_FILE_NAME, change)], &message, false)
+    }
+
+    /// `time` in the configured timezone, or in the local one.
+    pub fn local_time(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self.config.timezone {
+            Some(tz) => time.with_timezone(&tz).fixed_offset(),
+            None => time.with_timezone(&Local).fixed_offset(),
+        }
+    }
+}
+
+/// The user's settings file, in `XDG_CONFIG_HOME`, which defaults to `~/.config`.
+pub fn user_config_path() -> PathBuf {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"),
+    };
+    config_dir.join(USER_CONFIG_PATH)
+}
+
+/// Reads a settings file, which may not exist.
+fn read_document(path: &Path) -> Result<Option<DocumentMut>> {
+    match fs::read_to_string(path) {
+        Ok(text) => match text.parse() {
+            Ok(doc) => Ok(Some(doc)),
+            Err(e) => Err(DearyError::Config(format!("{}: {}", path.display(), e))),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(DearyError::from(e)),
+    }
+}
+
+/// Sets `key` in the settings file at `path`, keeping the rest of it, comments included, as it
+/// is. The file is only written if the new settings are valid.
+fn set_in_file(path: &Path, key: &str, values: &[&str], in_diary: bool) -> Result<()> {
+    let key = check_key(key)?;
+    if in_diary && USER_ONLY_KEYS.contains(&key) {
+        return Err(invalid(key, "can only be set in the user's settings"));
+    }
+    let value = if key == "editor.args" {
+        Value::from(values.iter().copied().collect::<Array>())
+    } else {
+        match values {
+            [value] => Value::from(*value),
+            _ => return Err(invalid(key, "takes a single value")),
+        }
+    };
+
+    let mut doc = read_document(path)?.unwrap_or_default();
+    let mut table = doc.as_table_mut();
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap();
+    for part in parts {
+        let item = table
+            .entry(part)
+            .or_insert_with(|| TomlItem::Table(Table::new()));
+        table = match item.as_table_mut() {
+            Some(table) => table,
+            None => return Err(invalid(part, "must be a table")),
+        };
+    }
+    table.insert(last, TomlItem::Value(value));
+    Config::default().apply(&doc, in_diary)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, doc.to_string())?;
+    Ok(())
+}
+
+fn find_item<'a>(doc: &'a DocumentMut, key: &str) -> Option<&'a TomlItem> {
+    let mut item = doc.as_item();
+    for part in key.split('.') {
+        item = item.get(part)?;
+    }
+    Some(item)
+}
+
+fn check_key(key: &str) -> Result<&'static str> {
+    match CONFIG_KEYS.iter().find(|k| **k == key) {
+        Some(key) => Ok(key),
+        None => Err(DearyError::Config(format!("Unknown setting {}", key))),
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> DearyError {
+    DearyError::Config(format!("Setting {} {}", key, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::DearyError;
+
+    fn entry_name_format(format: &str) -> Result<Option<String>, String> {
+        let mut doc = toml_edit::DocumentMut::new();
+        doc["entry_name_format"] = toml_edit::value(format);
+        let mut config = Config::default();
+        match config.apply(&doc, true) {
+            Ok(()) => Ok(config.entry_name_format),
+            Err(DearyError::Config(message)) => Err(message),
+            Err(e) => panic!("Unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn entry_name_formats_must_make_file_names() {
+        assert_eq!(
+            entry_name_format("%Y%m%d-%H%M%S"),
+            Ok(Some("%Y%m%d-%H%M%S".to_string()))
+        );
+        assert_eq!(
+            entry_name_format("entry %F %T"),
+            Ok(Some("entry %F %T".to_string()))
+        );
+        let invalid = Err("Setting entry_name_format does not make valid file names".to_string());
+        for format in &[
+            "", ".%Y", "%Y/%m", "%D", "%Y%t%m", "%Y%n", "%Y\u{7f}", "\r%Y",
+        ] {
+            assert_eq!(entry_name_format(format), invalid, "{:?}", format);
+        }
+        assert_eq!(
+            entry_name_format("%Y%Q"),
+            Err("Setting entry_name_format is not a valid date format".to_string())
+        );
+    }
+}
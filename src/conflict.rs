@@ -0,0 +1,332 @@
+//! Resolving conflicts between concurrent changes to entries, which git cannot merge because
+//! it only sees ciphertext.
+
+use crate::metadata::{format_metadata_index, parse_metadata_index};
+use crate::{
+    format_index, parse_index, Cipher, Deary, DearyError, EntryInfo, Metadata, Result,
+    INDEX_FILE_NAME, METADATA_FILE_NAME,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// The bits of `IndexEntry::flags` holding the merge stage.
+const INDEX_STAGE_MASK: u16 = 0x3000;
+/// The lines around conflicting changes, in order, as the editor gets them.
+const CONFLICT_MARKERS: &[&str] = &["<<<<<<<", "=======", ">>>>>>>"];
+
+impl<C: Cipher> Deary<C> {
+    /// Resolves the conflicts in `index`, the result of merging two commits.
+    ///
+    /// Conflicting entries are decrypted and merged line by line; if the same lines were changed
+    /// on both sides, the merge is opened in the editor, with conflict markers, for the user to
+    /// resolve. It is opened again as long as markers are left, unless it was saved unchanged,
+    /// which gives up on the merge. An entry edited on one side and deleted on the other is
+    /// kept. The index of a private diary gets the entries of both sides, and the metadata index
+    /// is merged entry by entry.
+    pub(crate) fn resolve_conflicts(&self, index: &mut git2::Index) -> Result<()> {
+        let mut conflicts = vec![];
+        for conflict in index.conflicts()? {
+            conflicts.push(conflict?);
+        }
+
+        let mut private_index = None;
+        let mut metadata_index = None;
+        // Texts of entries merged on both sides, by file name
+        let mut merged_texts = HashMap::new();
+        for conflict in conflicts {
+            let path = match conflict.our.as_ref().or(conflict.their.as_ref()) {
+                Some(entry) => entry_path(entry),
+                None => continue,
+            };
+            if path == Path::new(INDEX_FILE_NAME) {
+                private_index = Some(conflict);
+                continue;
+            }
+            if path == Path::new(METADATA_FILE_NAME) {
+                metadata_index = Some(conflict);
+                continue;
+            }
+            if path.to_string_lossy().starts_with('.') {
+                return Err(DearyError::Sync(format!(
+                    "Conflicting changes to {}",
+                    path.display()
+                )));
+            }
+
+            let git2::IndexConflict {
+                ancestor,
+                our,
+                their,
+            } = conflict;
+            let (entry, id) = match (our, their) {
+                (Some(ours), Some(theirs)) => {
+                    let text = self.merge_texts(&path, ancestor.as_ref(), &ours, &theirs)?;
+                    let ciphertext = self.encrypt_text(&text, &self.recipients()?)?;
+                    merged_texts.insert(path.to_string_lossy().into_owned(), text);
+                    (ours, self.repo.blob(&ciphertext)?)
+                }
+                (Some(entry), None) | (None, Some(entry)) => {
+                    let id = entry.id;
+                    (entry, id)
+                }
+                (None, None) => continue,
+            };
+            resolve(index, entry, id)?;
+        }
+
+        if let Some(conflict) = private_index {
+            let mut entries = vec![];
+            for side in [&conflict.our, &conflict.their].iter().copied().flatten() {
+                for entry in parse_index(&self.decrypt_blob(side)?) {
+                    if !entries.contains(&entry) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            // Entries deleted on either side are gone from the merged tree
+            entries.retain(|(_, file_name)| index.get_path(Path::new(file_name), 0).is_some());
+            entries.sort();
+
+            let ciphertext = self.encrypt_text(&format_index(&entries), &self.recipients()?)?;
+            let entry = conflict.our.or(conflict.their).unwrap();
+            resolve(index, entry, self.repo.blob(&ciphertext)?)?;
+        }
+
+        if let Some(conflict) = metadata_index {
+            self.merge_metadata(index, conflict, &merged_texts)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a conflict in the metadata index. The metadata of an entry changed on one side
+    /// only is taken from that side; that of an entry changed on both sides is parsed from its
+    /// merged text. Entries gone from the merged tree are dropped.
+    fn merge_metadata(
+        &self,
+        index: &mut git2::Index,
+        conflict: git2::IndexConflict,
+        merged_texts: &HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        let read = |side: &Option<git2::IndexEntry>| -> Result<Vec<EntryInfo>> {
+            match side {
+                Some(entry) => Ok(parse_metadata_index(&self.decrypt_blob(entry)?)),
+                None => Ok(vec![]),
+            }
+        };
+        let ancestor = read(&conflict.ancestor)?;
+        let ours = read(&conflict.our)?;
+        let theirs = read(&conflict.their)?;
+
+        // In a private diary, entries are stored under the file names in the (merged) index
+        let files = match index.get_path(Path::new(INDEX_FILE_NAME), 0) {
+            Some(entry) => parse_index(&self.decrypt_blob(&entry)?),
+            None => vec![],
+        };
+
+        let mut names: Vec<&str> = vec![];
+        for entry in ours.iter().chain(&theirs) {
+            if !names.contains(&entry.name.as_str()) {
+                names.push(&entry.name);
+            }
+        }
+
+        let mut entries = vec![];
+        for name in names {
+            let file_name = match files.iter().find(|(n, _)| n == name) {
+                Some((_, file_name)) => file_name.as_str(),
+                None => name,
+            };
+            if index.get_path(Path::new(file_name), 0).is_none() {
+                continue;
+            }
+
+            let find = |side: &[EntryInfo]| side.iter().find(|e| e.name == name).cloned();
+            let (ancestor, ours, theirs) = (find(&ancestor), find(&ours), find(&theirs));
+            let entry = match (merged_texts.get(file_name), ours.clone().or(theirs.clone())) {
+                (Some(text), Some(entry)) => Some(EntryInfo {
+                    size: Some(text.len() as u64),
+                    metadata: Metadata::parse(text),
+                    ..entry
+                }),
+                _ if ours == ancestor => theirs,
+                _ => ours.or(theirs),
+            };
+            entries.extend(entry);
+        }
+
+        let ciphertext =
+            self.encrypt_text(&format_metadata_index(&entries), &self.recipients()?)?;
+        let entry = conflict.our.or(conflict.their).unwrap();
+        resolve(index, entry, self.repo.blob(&ciphertext)?)
+    }
+
+    fn merge_texts(
+        &self,
+        path: &Path,
+        ancestor: Option<&git2::IndexEntry>,
+        ours: &git2::IndexEntry,
+        theirs: &git2::IndexEntry,
+    ) -> Result<Vec<u8>> {
+        let base = match ancestor {
+            Some(entry) => self.decrypt_blob(entry)?,
+            None => vec![],
+        };
+        let ours = self.decrypt_blob(ours)?;
+        let theirs = self.decrypt_blob(theirs)?;
+
+        match diffy::merge_bytes(&base, &ours, &theirs) {
+            Ok(merged) => Ok(merged),
+            Err(conflicting) => {
+                let mut tmp_file = self.tmp_file()?;
+                tmp_file.write_all(&conflicting)?;
+                let mut text = conflicting;
+                loop {
+                    self.open_editor(tmp_file.path())?;
+                    let merged = fs::read(tmp_file.path())?;
+                    if !has_conflict_markers(&merged) {
+                        tmp_file.close().unwrap();
+                        return Ok(merged);
+                    }
+                    if merged == text {
+                        tmp_file.close().unwrap();
+                        return Err(DearyError::Sync(format!(
+                            "Conflicting changes to {} were not resolved",
+                            path.display()
+                        )));
+                    }
+                    text = merged;
+                }
+            }
+        }
+    }
+
+    fn decrypt_blob(&self, entry: &git2::IndexEntry) -> Result<Vec<u8>> {
+        self.cipher
+            .decrypt(self.repo.find_blob(entry.id)?.content())
+    }
+}
+
+/// Tells whether `text` has every conflict marker, in order, at the start of a line.
+fn has_conflict_markers(text: &[u8]) -> bool {
+    let mut markers = CONFLICT_MARKERS.iter().peekable();
+    for line in text.split(|b| *b == b'\n') {
+        if markers
+            .next_if(|m| line.starts_with(m.as_bytes()))
+            .is_some()
+            && markers.peek().is_none()
+        {
+            return true;
+        }
+    }
+    false
+}
+
+fn entry_path(entry: &git2::IndexEntry) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned())
+}
+
+/// Replaces the conflicting stages of `entry`'s path with the blob `id`.
+fn resolve(index: &mut git2::Index, mut entry: git2::IndexEntry, id: git2::Oid) -> Result<()> {
+    let path = entry_path(&entry);
+    for stage in 1..=3 {
+        // Not every stage is present, e.g. there is no ancestor for an entry added on both sides
+        let _ = index.remove(&path, stage);
+    }
+    entry.id = id;
+    entry.flags &= !INDEX_STAGE_MASK;
+    index.add(&entry)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{mock_clone, mock_diary, mock_editor, mock_entry, MockCipher};
+    use crate::{Deary, DearyError};
+    use std::path::Path;
+
+    /// A diary with an entry, and a clone of it.
+    fn diaries(dir: &Path, text: &str) -> (Deary<MockCipher>, Deary<MockCipher>, String) {
+        let mut origin = mock_diary(&dir.join("origin"), &["alice"]);
+        let name = mock_entry(&mut origin, text);
+        let clone = mock_clone(&dir.join("origin"), &dir.join("clone"));
+        (origin, clone, name)
+    }
+
+    fn edit(deary: &mut Deary<MockCipher>, name: &str, text: &str) {
+        mock_editor(deary, text);
+        deary.update_entry(name).unwrap();
+    }
+
+    fn text(deary: &Deary<MockCipher>, name: &str) -> String {
+        String::from_utf8(deary.read_entry(name).unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn changes_to_different_lines_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut origin, mut clone, name) = diaries(dir.path(), "one\ntwo\nthree\nfour\nfive\n");
+        edit(&mut origin, &name, "ONE\ntwo\nthree\nfour\nfive\n");
+        edit(&mut clone, &name, "one\ntwo\nthree\nfour\nFIVE\n");
+        let other = mock_entry(&mut origin, "other entry");
+
+        clone.pull(None).unwrap();
+        assert_eq!(clone.list_entries().unwrap(), vec![name.clone(), other]);
+        assert_eq!(text(&clone, &name), "ONE\ntwo\nthree\nfour\nFIVE\n");
+        let head = clone.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent_count(), 2);
+    }
+
+    #[test]
+    fn entries_edited_on_one_side_and_deleted_on_the_other_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut origin, mut clone, first) = diaries(dir.path(), "first");
+        let second = mock_entry(&mut origin, "second");
+        clone.pull(None).unwrap();
+
+        origin.delete_entry(&first).unwrap();
+        edit(&mut origin, &second, "second, edited");
+        edit(&mut clone, &first, "first, edited");
+        clone.delete_entry(&second).unwrap();
+
+        clone.pull(None).unwrap();
+        assert_eq!(
+            clone.list_entries().unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(text(&clone, &first), "first, edited");
+        assert_eq!(text(&clone, &second), "second, edited");
+    }
+
+    #[test]
+    fn conflicts_are_resolved_in_the_editor_until_no_markers_are_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut origin, mut clone, name) = diaries(dir.path(), "one\n");
+        edit(&mut origin, &name, "ONE\n");
+        edit(&mut clone, &name, "One\n");
+        let head = clone.repo.head().unwrap().target();
+
+        // Saving the conflicts unchanged gives up
+        clone.config.editor_args = vec!["-c".to_string(), "true".to_string()];
+        match clone.pull(None) {
+            Err(DearyError::Sync(_)) => (),
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+        assert_eq!(clone.repo.head().unwrap().target(), head);
+        assert_eq!(text(&clone, &name), "One\n");
+
+        // The first edit leaves markers, the second one resolves them
+        let edited = dir.path().join("edited");
+        let script = "if [ -e \"$0\" ]; then printf 'ONE, One\\n' > \"$1\"; \
+                      else touch \"$0\"; echo more >> \"$1\"; fi";
+        clone.config.editor_args = vec![
+            "-c".to_string(),
+            script.to_string(),
+            edited.to_string_lossy().into_owned(),
+        ];
+        clone.pull(None).unwrap();
+        assert!(edited.exists());
+        assert_eq!(text(&clone, &name), "ONE, One\n");
+    }
+}
@@ -0,0 +1,334 @@
+//! Periods of time to select entries by, given in the configured or the local timezone.
+
+use crate::{Cipher, Deary, DearyError, Result};
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc, Weekday,
+};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const LATEST_ENTRY: &str = "latest";
+const DATE_TIME_FORMATS: &[(&str, i64)] = &[
+    ("%Y-%m-%d %H:%M:%S", 1),
+    ("%Y-%m-%dT%H:%M:%S", 1),
+    ("%Y-%m-%d %H:%M", 60),
+    ("%Y-%m-%dT%H:%M", 60),
+];
+
+/// A period of time, from `start` up to, but not including, `end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Period {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Period {
+    /// Parses a period in the local timezone, one of
+    ///
+    /// - a year, month or day: `2024`, `2024-03`, `2024-03-15`
+    /// - a minute or second: `2024-03-15 08:30`, `2024-03-15T08:30:12`
+    /// - `today`, `yesterday` or `N days ago`
+    /// - the last day of a week: `friday` (which may be today), `last friday` (which is not)
+    /// - `this week`, `last week`, and likewise for `month` and `year`; weeks start on Monday
+    pub fn parse(expr: &str) -> Result<Period> {
+        Period::parse_in(expr, &Local)
+    }
+
+    /// Parses a period like [`Period::parse`], in timezone `tz`.
+    pub fn parse_in<Tz: TimeZone>(expr: &str, tz: &Tz) -> Result<Period> {
+        Period::parse_at(expr, tz, Utc::now())
+    }
+
+    /// Parses a period like [`Period::parse_in`], as if it were `now`.
+    fn parse_at<Tz: TimeZone>(expr: &str, tz: &Tz, now: DateTime<Utc>) -> Result<Period> {
+        let expr = expr.trim();
+        let invalid = || DearyError::InvalidInput(format!("Invalid date: {}", expr));
+        let today = now.with_timezone(tz).date_naive();
+
+        let lowercase = expr.to_lowercase();
+        let words: Vec<&str> = lowercase.split_whitespace().collect();
+        let period = match words.as_slice() {
+            ["today"] => Period::days(today, 1, tz),
+            ["yesterday"] => Period::days(today - Duration::days(1), 1, tz),
+            [n, "day", "ago"] | [n, "days", "ago"] => match n.parse() {
+                Ok(n) => Duration::try_days(n)
+                    .and_then(|days| today.checked_sub_signed(days))
+                    .and_then(|day| Period::days(day, 1, tz)),
+                Err(_) => None,
+            },
+            ["this", unit] => Period::calendar(today, unit, 0, tz),
+            ["last", unit] => match unit.parse::<Weekday>() {
+                Ok(weekday) => {
+                    Period::days(last_weekday(today - Duration::days(1), weekday), 1, tz)
+                }
+                Err(_) => Period::calendar(today, unit, -1, tz),
+            },
+            [word] => match word.parse::<Weekday>() {
+                Ok(weekday) => Period::days(last_weekday(today, weekday), 1, tz),
+                Err(_) => Period::parse_date(expr, tz),
+            },
+            _ => Period::parse_date(expr, tz),
+        };
+        period.ok_or_else(invalid)
+    }
+
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.start <= time && time < self.end
+    }
+
+    fn parse_date<Tz: TimeZone>(expr: &str, tz: &Tz) -> Option<Period> {
+        if let Ok(date) = NaiveDate::parse_from_str(expr, DATE_FORMAT) {
+            return Period::days(date, 1, tz);
+        }
+        if let Ok(month) = NaiveDate::parse_from_str(&format!("{}-01", expr), DATE_FORMAT) {
+            return Period::dates(month, add_months(month, 1)?, tz);
+        }
+        if expr.len() == 4 && expr.chars().all(|c| c.is_ascii_digit()) {
+            let year = NaiveDate::from_ymd_opt(expr.parse().ok()?, 1, 1)?;
+            return Period::dates(year, add_months(year, 12)?, tz);
+        }
+        for (format, seconds) in DATE_TIME_FORMATS {
+            if let Ok(start) = NaiveDateTime::parse_from_str(expr, format) {
+                return Period::local(start, start + Duration::seconds(*seconds), tz);
+            }
+        }
+        None
+    }
+
+    /// The week, month or year `offset` of them away from the one `today` is in.
+    fn calendar<Tz: TimeZone>(
+        today: NaiveDate,
+        unit: &str,
+        offset: i32,
+        tz: &Tz,
+    ) -> Option<Period> {
+        match unit {
+            "week" => {
+                let monday = today - Duration::days(today.weekday().num_days_from_monday().into());
+                Period::days(monday + Duration::weeks(offset.into()), 7, tz)
+            }
+            "month" => {
+                let month = add_months(today.with_day(1)?, offset)?;
+                Period::dates(month, add_months(month, 1)?, tz)
+            }
+            "year" => {
+                let year = NaiveDate::from_ymd_opt(today.year() + offset, 1, 1)?;
+                Period::dates(year, add_months(year, 12)?, tz)
+            }
+            _ => None,
+        }
+    }
+
+    fn days<Tz: TimeZone>(start: NaiveDate, days: i64, tz: &Tz) -> Option<Period> {
+        Period::dates(start, start.checked_add_signed(Duration::days(days))?, tz)
+    }
+
+    fn dates<Tz: TimeZone>(start: NaiveDate, end: NaiveDate, tz: &Tz) -> Option<Period> {
+        Period::local(start.and_hms_opt(0, 0, 0)?, end.and_hms_opt(0, 0, 0)?, tz)
+    }
+
+    /// The period between two times in `tz`. A time skipped by a daylight saving time change
+    /// is taken as the first one after the change, so that the period may be empty.
+    fn local<Tz: TimeZone>(start: NaiveDateTime, end: NaiveDateTime, tz: &Tz) -> Option<Period> {
+        let earliest = |time: NaiveDateTime| tz.from_local_datetime(&time).earliest();
+        let to_utc = |time: NaiveDateTime| {
+            let time = earliest(time).or_else(|| {
+                // Changes happen on the minute, and skip less than a day
+                let minute = time.with_second(0)?.with_nanosecond(0)?;
+                (1..=24 * 60).find_map(|minutes| {
+                    earliest(minute.checked_add_signed(Duration::minutes(minutes))?)
+                })
+            })?;
+            Some(time.with_timezone(&Utc))
+        };
+        Some(Period {
+            start: to_utc(start)?,
+            end: to_utc(end)?,
+        })
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Parses a period, see [`Period::parse`], in the configured timezone.
+    pub fn parse_period(&self, expr: &str) -> Result<Period> {
+        match self.config.timezone {
+            Some(tz) => Period::parse_in(expr, &tz),
+            None => Period::parse(expr),
+        }
+    }
+
+    /// Finds the entry `expr` refers to, which is one of
+    ///
+    /// - the entry's name
+    /// - `latest`, or `-N` for the Nth newest entry (`-1` being the latest)
+    /// - a period, see [`Period::parse`], for the newest entry created in it
+    pub fn find_entry(&self, expr: &str) -> Result<String> {
+        let not_found = || DearyError::EntryNotFound(expr.to_string());
+        if self.list_entries()?.iter().any(|name| name == expr) {
+            return Ok(expr.to_string());
+        }
+
+        let entries = self.list_entries_with_metadata()?;
+        let mut newest_first = entries.iter().rev();
+        let entry = if expr == LATEST_ENTRY {
+            newest_first.next()
+        } else if let Some(n) = expr.strip_prefix('-').and_then(|n| n.parse::<usize>().ok()) {
+            newest_first.nth(n.checked_sub(1).ok_or_else(not_found)?)
+        } else {
+            let period = self.parse_period(expr).map_err(|_| not_found())?;
+            newest_first.find(|e| e.created.is_some_and(|c| period.contains(c)))
+        };
+        match entry {
+            Some(entry) => Ok(entry.name.clone()),
+            None => Err(not_found()),
+        }
+    }
+}
+
+/// The last `weekday` on or before `day`.
+fn last_weekday(day: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days_since =
+        (7 + day.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+    day - Duration::days(days_since.into())
+}
+
+/// The first day of the month `months` months away from the one `date` is the first day of.
+fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let month0 = date.year() * 12 + date.month0() as i32 + months;
+    NaiveDate::from_ymd_opt(month0.div_euclid(12), month0.rem_euclid(12) as u32 + 1, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_months, last_weekday, Period};
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc, Weekday};
+    use chrono_tz::America::Santiago;
+    use chrono_tz::Europe::Amsterdam;
+    use chrono_tz::Tz;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn time(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Tz> {
+        Amsterdam.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    /// Parses `expr` in Amsterdam, at noon on `today`.
+    fn parse(expr: &str, today: NaiveDate) -> Option<Period> {
+        let now = Amsterdam
+            .from_local_datetime(&today.and_hms_opt(12, 0, 0).unwrap())
+            .unwrap();
+        Period::parse_at(expr, &Amsterdam, now.with_timezone(&Utc)).ok()
+    }
+
+    fn day(y: i32, m: u32, d: u32) -> Period {
+        let start = time(y, m, d, 0, 0);
+        Period {
+            start: start.with_timezone(&Utc),
+            end: (start.date_naive() + chrono::Duration::days(1))
+                .and_hms_opt(0, 0, 0)
+                .and_then(|t| Amsterdam.from_local_datetime(&t).earliest())
+                .unwrap()
+                .with_timezone(&Utc),
+        }
+    }
+
+    #[test]
+    fn weekday_is_today_but_last_weekday_is_not() {
+        // 2024-03-15 is a Friday
+        let friday = date(2024, 3, 15);
+        assert_eq!(last_weekday(friday, Weekday::Fri), friday);
+        assert_eq!(last_weekday(friday, Weekday::Sat), date(2024, 3, 9));
+        assert_eq!(parse("friday", friday), Some(day(2024, 3, 15)));
+        assert_eq!(parse("last friday", friday), Some(day(2024, 3, 8)));
+        assert_eq!(parse("Last Thursday", friday), Some(day(2024, 3, 14)));
+    }
+
+    #[test]
+    fn days_ago_may_be_too_long_ago() {
+        let today = date(2024, 3, 1);
+        assert_eq!(parse("1 day ago", today), Some(day(2024, 2, 29)));
+        assert_eq!(parse("366 days ago", today), Some(day(2023, 3, 1)));
+        assert_eq!(parse("100000000 days ago", today), None);
+        assert_eq!(parse("-100000000 days ago", today), None);
+        assert_eq!(parse(&format!("{} days ago", i64::MAX), today), None);
+    }
+
+    #[test]
+    fn months_roll_over_years() {
+        assert_eq!(add_months(date(2024, 1, 1), -1), Some(date(2023, 12, 1)));
+        assert_eq!(add_months(date(2023, 12, 1), 1), Some(date(2024, 1, 1)));
+        assert_eq!(add_months(date(2024, 1, 1), -13), Some(date(2022, 12, 1)));
+        assert_eq!(add_months(date(2024, 3, 1), 12), Some(date(2025, 3, 1)));
+
+        let last_month = parse("last month", date(2024, 1, 20)).unwrap();
+        assert_eq!(last_month.start, time(2023, 12, 1, 0, 0));
+        assert_eq!(last_month.end, time(2024, 1, 1, 0, 0));
+        let last_year = parse("last year", date(2024, 1, 20)).unwrap();
+        assert_eq!(last_year.start, time(2023, 1, 1, 0, 0));
+        assert_eq!(last_year.end, time(2024, 1, 1, 0, 0));
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let this_week = parse("this week", date(2024, 3, 17)).unwrap();
+        assert_eq!(this_week.start, time(2024, 3, 11, 0, 0));
+        assert_eq!(this_week.end, time(2024, 3, 18, 0, 0));
+    }
+
+    #[test]
+    fn periods_are_in_the_given_timezone() {
+        let today = date(2024, 6, 1);
+        let month = parse("2024-03", today).unwrap();
+        assert_eq!(
+            month.start,
+            Utc.with_ymd_and_hms(2024, 2, 29, 23, 0, 0).unwrap()
+        );
+        assert_eq!(
+            month.end,
+            Utc.with_ymd_and_hms(2024, 3, 31, 22, 0, 0).unwrap()
+        );
+
+        let minute = parse("2024-03-15 08:30", today).unwrap();
+        assert_eq!(minute.start, time(2024, 3, 15, 8, 30));
+        assert_eq!(minute.end, time(2024, 3, 15, 8, 31));
+        assert!(parse("2024-13", today).is_none());
+        assert!(parse("someday", today).is_none());
+    }
+
+    #[test]
+    fn daylight_saving_time_changes() {
+        let today = date(2024, 6, 1);
+        // Clocks went from 02:00 to 03:00 on 2024-03-31, which was 23 hours long
+        let short_day = parse("2024-03-31", today).unwrap();
+        assert_eq!(short_day.end - short_day.start, chrono::Duration::hours(23));
+        // where 02:30 did not exist
+        let skipped = parse("2024-03-31 02:30", today).unwrap();
+        assert_eq!(skipped.start, time(2024, 3, 31, 3, 0));
+        assert_eq!(skipped.end, skipped.start);
+        // and back from 03:00 to 02:00 on 2024-10-27, where 02:30 is the earlier one
+        let long_day = parse("2024-10-27", today).unwrap();
+        assert_eq!(long_day.end - long_day.start, chrono::Duration::hours(25));
+        let ambiguous = parse("2024-10-27 02:30", today).unwrap();
+        assert_eq!(
+            ambiguous.start,
+            Utc.with_ymd_and_hms(2024, 10, 27, 0, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn days_may_start_after_midnight() {
+        // Clocks in Santiago went from 00:00 to 01:00 on 2024-09-08
+        let now = Utc.with_ymd_and_hms(2024, 10, 1, 12, 0, 0).unwrap();
+        let parse = |expr| Period::parse_at(expr, &Santiago, now).unwrap();
+        let utc = |d, h| Utc.with_ymd_and_hms(2024, 9, d, h, 0, 0).unwrap();
+        assert_eq!(parse("2024-09-07").start, utc(7, 4));
+        assert_eq!(parse("2024-09-07").end, utc(8, 4));
+        assert_eq!(parse("2024-09-08").start, utc(8, 4));
+        assert_eq!(parse("2024-09-08").end, utc(9, 3));
+        assert_eq!(
+            parse("2024-09").end,
+            Utc.with_ymd_and_hms(2024, 10, 1, 3, 0, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Diffs between decrypted revisions of an entry, which `git diff` can only show as changed
+//! ciphertext.
+
+use crate::{Cipher, Deary, Result};
+use similar::udiff::UnifiedHunkHeader;
+use similar::{ChangeTag, TextDiff};
+
+const CONTEXT_LINES: usize = 3;
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// How [`Deary::diff_entry`] renders a diff.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiffStyle {
+    /// Mark changed words within lines, like `git diff --word-diff`, instead of whole lines.
+    pub words: bool,
+    /// Color the diff with ANSI escape codes.
+    pub color: bool,
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Diffs an entry between two revisions, in unified format. `from` defaults to the revision
+    /// before the entry's last change, and `to` to the current entry.
+    pub fn diff_entry(
+        &self,
+        name: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+        style: DiffStyle,
+    ) -> Result<String> {
+        let (old_label, old) = match from {
+            Some(rev) => (rev.to_string(), self.text_at(name, rev)?),
+            None => match self.entry_history(name)?.get(1) {
+                Some(previous) => (
+                    previous.id[..7].to_string(),
+                    self.text_at(name, &previous.id)?,
+                ),
+                None => ("(none)".to_string(), vec![]),
+            },
+        };
+        let (new_label, new) = match to {
+            Some(rev) => (rev.to_string(), self.text_at(name, rev)?),
+            None => (
+                "current".to_string(),
+                self.decrypt_entry(&self.entry_path(name)?)?,
+            ),
+        };
+
+        Ok(format_diff(
+            &String::from_utf8_lossy(&old),
+            &String::from_utf8_lossy(&new),
+            &format!("{} {}", name, old_label),
+            &format!("{} {}", name, new_label),
+            style,
+        ))
+    }
+}
+
+fn format_diff(old: &str, new: &str, old_label: &str, new_label: &str, style: DiffStyle) -> String {
+    let paint = |color: &str, text: &str| {
+        if style.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let diff = TextDiff::from_lines(old, new);
+    let groups = diff.grouped_ops(CONTEXT_LINES);
+    if groups.is_empty() {
+        return String::new();
+    }
+
+    let mut output = paint(BOLD, &format!("--- {}\n+++ {}", old_label, new_label));
+    output.push('\n');
+    for group in groups {
+        output.push_str(&paint(CYAN, &UnifiedHunkHeader::new(&group).to_string()));
+        output.push('\n');
+
+        if style.words {
+            let old_range = group[0].old_range().start..group[group.len() - 1].old_range().end;
+            let new_range = group[0].new_range().start..group[group.len() - 1].new_range().end;
+            let old_hunk = diff.old_slices()[old_range].concat();
+            let new_hunk = diff.new_slices()[new_range].concat();
+
+            // Merge consecutive changes of the same kind, so that "{+new line+}" is marked once
+            let mut runs: Vec<(ChangeTag, String)> = vec![];
+            for change in TextDiff::from_words(&old_hunk, &new_hunk).iter_all_changes() {
+                match runs.last_mut() {
+                    Some((tag, text)) if *tag == change.tag() => text.push_str(change.value()),
+                    _ => runs.push((change.tag(), change.value().to_string())),
+                }
+            }
+            for (tag, text) in runs {
+                let (color, open, close) = match tag {
+                    ChangeTag::Equal => {
+                        output.push_str(&text);
+                        continue;
+                    }
+                    ChangeTag::Delete => (RED, "[-", "-]"),
+                    ChangeTag::Insert => (GREEN, "{+", "+}"),
+                };
+                // Keep line breaks outside the markers
+                for line in text.split_inclusive('\n') {
+                    let words = line.trim_end_matches('\n');
+                    if !words.is_empty() {
+                        if style.color {
+                            output.push_str(&paint(color, words));
+                        } else {
+                            output.push_str(&format!("{}{}{}", open, words, close));
+                        }
+                    }
+                    output.push_str(&line[words.len()..]);
+                }
+            }
+            if !output.ends_with('\n') {
+                output.push('\n');
+            }
+        } else {
+            for op in &group {
+                for change in diff.iter_changes(op) {
+                    let line = format!("{}{}", change.tag(), change.value().trim_end_matches('\n'));
+                    match change.tag() {
+                        ChangeTag::Equal => output.push_str(&line),
+                        ChangeTag::Delete => output.push_str(&paint(RED, &line)),
+                        ChangeTag::Insert => output.push_str(&paint(GREEN, &line)),
+                    }
+                    output.push('\n');
+                    if change.missing_newline() {
+                        output.push_str("\\ No newline at end of file\n");
+                    }
+                }
+            }
+        }
+    }
+    output
+}
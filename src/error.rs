@@ -0,0 +1,135 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong in deary.
+///
+/// Each variant maps to a distinct process exit code (see [`DearyError::exit_code`]), so that
+/// scripts wrapping `deary` can react to specific failures.
+#[derive(Debug)]
+pub enum DearyError {
+    /// The diary repository does not exist (exit code 3).
+    RepoNotFound(PathBuf),
+    /// A diary repository already exists where a new one was to be created (exit code 4).
+    RepoExists(PathBuf),
+    /// There is no entry with the given name (exit code 5).
+    EntryNotFound(String),
+    /// The editor could not be found, or exited unsuccessfully (exit code 6).
+    Editor(String),
+    /// An entry could not be encrypted, e.g. because a recipient key is missing (exit code 7).
+    Encryption(String),
+    /// An entry could not be decrypted (wrong key, unavailable agent, corrupt file, ...). Carries
+    /// the backend's diagnostics, e.g. gpg's stderr (exit code 8).
+    Decryption(String),
+    /// A git operation on the repository failed (exit code 9).
+    Git(git2::Error),
+    /// Reading or writing a file failed (exit code 10).
+    Io(io::Error),
+    /// The environment or the diary's settings are invalid (exit code 11).
+    Config(String),
+    /// An entry's signature is missing, bad or made with an untrusted key (exit code 12).
+    Verification(String),
+    /// A commit could not be signed (exit code 13).
+    Signing(String),
+    /// Pushing to or pulling from a remote failed, e.g. because of conflicting changes
+    /// (exit code 14).
+    Sync(String),
+    /// An argument is invalid, e.g. a malformed date, tag or pattern, or an unknown revision
+    /// (exit code 15).
+    InvalidInput(String),
+    /// An entry with the name a new entry would get already exists (exit code 16).
+    EntryExists(String),
+    /// Any other failure (exit code 1).
+    Other(String),
+}
+
+impl DearyError {
+    pub fn new(msg: &str) -> DearyError {
+        DearyError::Other(msg.to_string())
+    }
+
+    /// The process exit code `deary` terminates with when failing with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DearyError::Other(_) => 1,
+            DearyError::RepoNotFound(_) => 3,
+            DearyError::RepoExists(_) => 4,
+            DearyError::EntryNotFound(_) => 5,
+            DearyError::Editor(_) => 6,
+            DearyError::Encryption(_) => 7,
+            DearyError::Decryption(_) => 8,
+            DearyError::Git(_) => 9,
+            DearyError::Io(_) => 10,
+            DearyError::Config(_) => 11,
+            DearyError::Verification(_) => 12,
+            DearyError::Signing(_) => 13,
+            DearyError::Sync(_) => 14,
+            DearyError::InvalidInput(_) => 15,
+            DearyError::EntryExists(_) => 16,
+        }
+    }
+}
+
+impl fmt::Display for DearyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DearyError::RepoNotFound(path) => {
+                write!(f, "Repository {} not found", path.display())
+            }
+            DearyError::RepoExists(path) => {
+                write!(f, "Repository {} already exists", path.display())
+            }
+            DearyError::EntryNotFound(name) => write!(f, "Entry {} not found", name),
+            DearyError::Editor(details) => write!(f, "Editor failed: {}", details),
+            DearyError::Encryption(details) => write!(f, "Encryption failed: {}", details),
+            DearyError::Decryption(details) => write!(f, "Decryption failed: {}", details),
+            DearyError::Git(e) => write!(f, "Git error: {}", e.message()),
+            DearyError::Io(e) => write!(f, "{}", e),
+            DearyError::Config(message) => write!(f, "Configuration error: {}", message),
+            DearyError::Verification(details) => {
+                write!(f, "Signature verification failed: {}", details)
+            }
+            DearyError::Signing(details) => write!(f, "Signing failed: {}", details),
+            DearyError::Sync(details) => write!(f, "Synchronization failed: {}", details),
+            DearyError::InvalidInput(message) => write!(f, "{}", message),
+            DearyError::EntryExists(name) => write!(f, "Entry {} already exists", name),
+            DearyError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for DearyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DearyError::Git(e) => Some(e),
+            DearyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for DearyError {
+    fn from(e: git2::Error) -> Self {
+        DearyError::Git(e)
+    }
+}
+
+impl From<io::Error> for DearyError {
+    fn from(e: io::Error) -> Self {
+        DearyError::Io(e)
+    }
+}
+
+impl From<env::VarError> for DearyError {
+    fn from(e: env::VarError) -> Self {
+        DearyError::Config(e.to_string())
+    }
+}
+
+impl From<which::Error> for DearyError {
+    fn from(e: which::Error) -> Self {
+        DearyError::Config(e.to_string())
+    }
+}
@@ -0,0 +1,168 @@
+//! Past revisions of entries, read from the commits that changed them.
+
+use crate::{Change, Cipher, Deary, DearyError, Result, Verification};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// A commit that changed an entry, see [`Deary::entry_history`].
+#[derive(Clone, Debug)]
+pub struct Revision {
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub summary: String,
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Lists the commits that changed an entry, newest first.
+    pub fn entry_history(&self, name: &str) -> Result<Vec<Revision>> {
+        self.history_from(name, self.repo.head()?.peel_to_commit()?.id(), false)
+    }
+
+    /// The last commit that changed an entry, as of `rev` (see [`Deary::read_entry_at`]) or
+    /// HEAD.
+    pub fn last_revision(&self, name: &str, rev: Option<&str>) -> Result<Option<Revision>> {
+        let start = match rev {
+            Some(rev) => self.find_revision(rev)?,
+            None => self.repo.head()?.peel_to_commit()?,
+        };
+        Ok(self.history_from(name, start.id(), true)?.pop())
+    }
+
+    /// The last commit that changed each entry, by entry name, found in a single walk through
+    /// the history.
+    pub fn last_revisions(&self) -> Result<HashMap<String, Revision>> {
+        let mut names: HashMap<String, String> = self
+            .entry_files()?
+            .into_iter()
+            .map(|(name, file_name)| (file_name, name))
+            .collect();
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_head()?;
+
+        let mut revisions = HashMap::new();
+        for oid in revwalk {
+            if names.is_empty() {
+                break;
+            }
+            let commit = self.repo.find_commit(oid?)?;
+            let parent_tree = match commit.parents().next() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+            let diff =
+                self.repo
+                    .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+            for delta in diff.deltas() {
+                let file_name = delta.new_file().path().and_then(|p| p.to_str());
+                if let Some(name) = file_name.and_then(|f| names.remove(f)) {
+                    revisions.insert(name, revision(&commit));
+                }
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Lists the commits that changed an entry, newest first, starting from commit `start`
+    /// and stopping after the first one if `last_only` is set.
+    fn history_from(&self, name: &str, start: git2::Oid, last_only: bool) -> Result<Vec<Revision>> {
+        let paths = self.history_paths(name)?;
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push(start)?;
+
+        let mut revisions = vec![];
+        for oid in revwalk {
+            let commit = self.repo.find_commit(oid?)?;
+            let changed = match commit.parents().next() {
+                Some(parent) => paths
+                    .iter()
+                    .any(|p| blob_id(&commit, p) != blob_id(&parent, p)),
+                None => paths.iter().any(|p| blob_id(&commit, p).is_some()),
+            };
+            if changed {
+                revisions.push(revision(&commit));
+                if last_only {
+                    break;
+                }
+            }
+        }
+        Ok(revisions)
+    }
+
+    /// Decrypts an entry as it was at `rev`, any revision git understands, such as a commit ID
+    /// from [`Deary::entry_history`] or `HEAD~2`.
+    pub fn read_entry_at(&self, name: &str, rev: &str) -> Result<(Vec<u8>, Verification)> {
+        let ciphertext = self.ciphertext_at(name, rev)?;
+        self.cipher.decrypt_and_verify(&ciphertext)
+    }
+
+    /// Replaces an entry with its content at `rev`, as a new commit.
+    pub fn restore_entry(&self, name: &str, rev: &str) -> Result<()> {
+        let file_name = self.entry_file(name)?;
+        let text = self.text_at(name, rev)?;
+
+        // Re-encrypt, as the recipients may have changed since
+        let ciphertext = self.encrypt_text(&text, &self.recipients()?)?;
+        File::create(self.repo_dir().join(&file_name))?.write_all(&ciphertext)?;
+        self.update_metadata(name, &text)?;
+        self.commit_entry(&file_name, Change::Edit)
+    }
+
+    /// Decrypts an entry as it was at `rev`, without verifying its signature.
+    pub(crate) fn text_at(&self, name: &str, rev: &str) -> Result<Vec<u8>> {
+        self.cipher.decrypt(&self.ciphertext_at(name, rev)?)
+    }
+
+    fn ciphertext_at(&self, name: &str, rev: &str) -> Result<Vec<u8>> {
+        let commit = self.find_revision(rev)?;
+        for path in self.history_paths(name)? {
+            if let Some(id) = blob_id(&commit, &path) {
+                return Ok(self.repo.find_blob(id)?.content().to_vec());
+            }
+        }
+        Err(DearyError::EntryNotFound(format!("{} at {}", name, rev)))
+    }
+
+    fn find_revision(&self, rev: &str) -> Result<git2::Commit<'_>> {
+        match self.repo.revparse_single(rev) {
+            Ok(object) => Ok(object.peel_to_commit()?),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Err(DearyError::InvalidInput(
+                format!("Revision {} not found", rev),
+            )),
+            Err(e) => Err(DearyError::from(e)),
+        }
+    }
+
+    /// The paths an entry has been stored at: its file, and, in a private diary, its name from
+    /// before `make_private` renamed it.
+    fn history_paths(&self, name: &str) -> Result<Vec<String>> {
+        let file_name = self.entry_file(name)?;
+        if file_name == name {
+            Ok(vec![file_name])
+        } else {
+            Ok(vec![file_name, name.to_string()])
+        }
+    }
+}
+
+pub(crate) fn revision(commit: &git2::Commit) -> Revision {
+    Revision {
+        id: commit.id().to_string(),
+        time: commit_time(commit),
+        summary: commit.summary().unwrap_or_default().to_string(),
+    }
+}
+
+pub(crate) fn commit_time(commit: &git2::Commit) -> DateTime<Utc> {
+    Utc.timestamp_opt(commit.time().seconds(), 0)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
+
+pub(crate) fn blob_id(commit: &git2::Commit, path: &str) -> Option<git2::Oid> {
+    let tree = commit.tree().ok()?;
+    let entry = tree.get_path(Path::new(path)).ok()?;
+    Some(entry.id())
+}
@@ -1,154 +1,342 @@
+mod cipher;
+mod config;
+mod conflict;
+mod dates;
+mod diff;
+mod error;
+mod history;
+mod metadata;
+mod purge;
+mod search;
+mod search_index;
+mod signing;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod trash;
+
+pub use cipher::{Age, Cipher, Gpg, OpenPgp, Verification};
+pub use config::{user_config_path, Config, CONFIG_KEYS};
+pub use dates::Period;
+pub use diff::DiffStyle;
+pub use error::DearyError;
+pub use history::Revision;
+pub use metadata::{EntryInfo, Metadata};
+pub use search::{SearchHit, SearchLine, SearchOptions};
+pub use signing::is_ssh_key;
+pub use trash::DeletedEntry;
+
 use chrono::Utc;
-use git2;
+use signing::CommitSigner;
 use std::collections::HashMap;
 use std::env;
-use std::fmt;
-use std::fs::{read_dir, remove_file, File};
+use std::fs::{self, read_dir, remove_file, File};
 use std::io;
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
 use std::result;
+use std::thread;
 use tempfile::NamedTempFile;
-use which;
 
 const EDITOR: &str = "vim";
-const GPG: &str = "gpg";
 const REPO_DIR: &str = ".deary";
+const DIARIES_DIR: &str = ".deary.d";
+const DEFAULT_DIARY: &str = "default";
+const DIARY_DIR_ENV: &str = "DEARY_DIR";
 const TMP_DIR: &str = "/dev/shm";
 const GPG_ID_FILE_NAME: &str = ".gpg_id";
-const GPG_OPTS: &[&str] = &[
-    "--quiet",
-    "--yes",
-    "--compress-algo=none",
-    "--no-encrypt-to",
-];
-
-type Result<T> = result::Result<T, DearyError>;
-
-#[derive(Debug)]
+const AGE_RECIPIENTS_FILE_NAME: &str = ".age_recipients";
+const INDEX_FILE_NAME: &str = ".index";
+const METADATA_FILE_NAME: &str = ".metadata";
+const ENTRY_NAME_FORMAT: &str = "%Y%m%d-%H%M%S";
+const PRIVATE_COMMIT_MESSAGE: &str = "Update diary";
+pub(crate) const SIGNING_KEY_CONFIG: &str = "deary.signingKey";
+const KEYRING_ENV: &str = "DEARY_KEYRING";
+const AGE_IDENTITY_ENV: &str = "DEARY_AGE_IDENTITY";
+const DEFAULT_AGE_IDENTITIES: &[&str] = &[".ssh/id_ed25519", ".ssh/id_rsa"];
+
+pub(crate) type Result<T> = result::Result<T, DearyError>;
+
+#[derive(Clone, Copy, Debug)]
 enum Change {
     Add,
     Edit,
     Delete,
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct DearyError {
-    message: String,
-}
-
-impl fmt::Display for DearyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
-    }
+/// The outcome of checking a single commit's signature, see [`Deary::verify_history`].
+pub struct CommitVerification {
+    pub id: String,
+    pub summary: String,
+    pub verification: Verification,
 }
 
-impl DearyError {
-    pub fn new(msg: &str) -> DearyError {
-        DearyError {
-            message: msg.to_string(),
-        }
-    }
-}
-
-impl From<git2::Error> for DearyError {
-    fn from(e: git2::Error) -> Self {
-        DearyError::new(&e.to_string())
-    }
-}
-
-impl From<io::Error> for DearyError {
-    fn from(e: io::Error) -> Self {
-        DearyError::new(&e.to_string())
-    }
-}
-
-impl From<env::VarError> for DearyError {
-    fn from(e: env::VarError) -> Self {
-        DearyError::new(&e.to_string())
-    }
-}
-
-impl From<which::Error> for DearyError {
-    fn from(e: which::Error) -> Self {
-        DearyError::new(&e.to_string())
-    }
-}
-
-pub struct Deary {
+pub struct Deary<C: Cipher = Box<dyn Cipher>> {
     repo: git2::Repository,
+    cipher: C,
+    config: Config,
 }
 
 impl Deary {
-    pub fn init(repo_path: &Path, gpg_id: &str, git_config: HashMap<&str, &str>) -> Result<()> {
+    /// Creates a new diary encrypted to `recipients`, which are either GPG key IDs (or email
+    /// addresses), or age or SSH public keys. See [`Deary::make_private`] for `private`.
+    pub fn init(
+        repo_path: &Path,
+        recipients: &[&str],
+        git_config: HashMap<&str, &str>,
+        private: bool,
+    ) -> Result<()> {
+        let age = match recipients.first() {
+            Some(r) => Age::is_recipient(r),
+            None => {
+                return Err(DearyError::Config(
+                    "At least one recipient is required".to_string(),
+                ))
+            }
+        };
+        if recipients.iter().any(|r| Age::is_recipient(r) != age) {
+            return Err(DearyError::Config(
+                "Cannot mix GPG and age recipients".to_string(),
+            ));
+        }
+
+        let config = Config::load(Some(repo_path))?;
         let repo = git2::Repository::init(repo_path)?;
-        let deary = Deary { repo };
+        let (cipher, file_name) = if age {
+            (age_cipher(), AGE_RECIPIENTS_FILE_NAME)
+        } else {
+            (default_cipher()?, GPG_ID_FILE_NAME)
+        };
+        let deary = Deary {
+            repo,
+            cipher,
+            config,
+        };
         deary.set_config(git_config)?;
-        deary.create_gpg_id_file(gpg_id)
+        deary.create_recipients_file(file_name, recipients)?;
+        if private {
+            deary.make_private()?;
+        }
+        Ok(())
     }
 
     pub fn new(repo_path: &Path) -> Result<Deary> {
-        let repo = git2::Repository::open(repo_path)?;
-        Ok(Deary { repo })
+        let cipher = if repo_path.join(AGE_RECIPIENTS_FILE_NAME).exists() {
+            age_cipher()
+        } else {
+            default_cipher()?
+        };
+        Deary::with_cipher(repo_path, cipher)
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    pub fn with_cipher(repo_path: &Path, cipher: C) -> Result<Deary<C>> {
+        let repo = match git2::Repository::open(repo_path) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                return Err(DearyError::RepoNotFound(repo_path.to_path_buf()))
+            }
+            Err(e) => return Err(DearyError::from(e)),
+        };
+        let config = Config::load(Some(repo_path))?;
+        Ok(Deary {
+            repo,
+            cipher,
+            config,
+        })
     }
 
     pub fn create_entry(&self) -> Result<()> {
-        let tmp_file = NamedTempFile::new_in(TMP_DIR)?;
-        let dt = Utc::now();
-        let file_name = dt.format("%Y%m%d-%H%M%S").to_string();
+        let name = self.new_entry_name()?;
+        let tmp_file = self.tmp_file()?;
+        let file_name = if self.is_private() {
+            random_file_name()
+        } else {
+            name.clone()
+        };
         let file_path = self.repo_dir().join(&file_name);
 
-        open_editor(&tmp_file.path())?;
-        encrypt_entry(tmp_file.path(), &file_path, &self.gpg_id()?)?;
+        self.open_editor(tmp_file.path())?;
+        self.encrypt_entry(tmp_file.path(), &file_path)?;
+        let text = fs::read(tmp_file.path())?;
         tmp_file.close().unwrap();
-        self.commit_change(&file_name, Change::Add, false)?;
-        Ok(())
+        self.update_metadata(&name, &text)?;
+        if self.is_private() {
+            let mut index = self.read_index()?;
+            index.push((name.clone(), file_name.clone()));
+            self.write_index(&index)?;
+        }
+        self.index_entry(&name, &text)?;
+        self.commit_entry(&file_name, Change::Add)
     }
 
-    pub fn read_entry(&self, name: &str) -> Result<Vec<u8>> {
-        let file_path = self.repo_dir().join(name);
-        decrypt_entry(&file_path)
+    /// Decrypts an entry, also verifying its signature, if it has one.
+    pub fn read_entry(&self, name: &str) -> Result<(Vec<u8>, Verification)> {
+        let file_path = self.entry_path(name)?;
+        let mut ciphertext = vec![];
+        File::open(file_path)?.read_to_end(&mut ciphertext)?;
+        self.cipher.decrypt_and_verify(&ciphertext)
     }
 
     pub fn update_entry(&self, name: &str) -> Result<()> {
-        let file_path = self.repo_dir().join(name);
-        let text = decrypt_entry(&file_path)?;
+        let file_name = self.entry_file(name)?;
+        let file_path = self.repo_dir().join(&file_name);
+        let text = self.decrypt_entry(&file_path)?;
 
-        let mut tmp_file = NamedTempFile::new_in(TMP_DIR)?;
+        let mut tmp_file = self.tmp_file()?;
         tmp_file.write_all(&text)?;
 
-        open_editor(tmp_file.path())?;
-        encrypt_entry(tmp_file.path(), &file_path, &self.gpg_id()?)?;
+        self.open_editor(tmp_file.path())?;
+        self.encrypt_entry(tmp_file.path(), &file_path)?;
+        let text = fs::read(tmp_file.path())?;
         tmp_file.close().unwrap();
-        self.commit_change(name, Change::Edit, false)?;
-        Ok(())
+        self.update_metadata(name, &text)?;
+        self.index_entry(name, &text)?;
+        self.commit_entry(&file_name, Change::Edit)
     }
 
     pub fn delete_entry(&self, name: &str) -> Result<()> {
-        let file_path = self.repo_dir().join(name);
-        remove_file(file_path)?;
-        self.commit_change(name, Change::Delete, false)
+        let file_name = self.entry_file(name)?;
+        remove_file(self.repo_dir().join(&file_name))?;
+        self.remove_metadata(name)?;
+        self.unindex_entry(name)?;
+        if self.is_private() {
+            let mut index = self.read_index()?;
+            index.retain(|(_, f)| *f != file_name);
+            self.write_index(&index)?;
+        }
+        self.commit_entry(&file_name, Change::Delete)
     }
 
+    /// Lists entry names, oldest first, as entries are named after the time they were created.
     pub fn list_entries(&self) -> Result<Vec<String>> {
-        let paths = read_dir(self.repo_dir())?;
-        let mut file_names = vec![];
+        Ok(self
+            .entry_files()?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// Tells whether the diary is in privacy mode, see [`Deary::make_private`].
+    pub fn is_private(&self) -> bool {
+        self.repo_dir().join(INDEX_FILE_NAME).exists()
+    }
+
+    /// Turns on privacy mode: entry files are renamed to random names, the mapping from entry
+    /// names (timestamps) to files is kept in an encrypted index, and commit messages no longer
+    /// mention entries. Entries already in the history keep their names there.
+    pub fn make_private(&self) -> Result<()> {
+        if self.is_private() {
+            return Err(DearyError::Config("Diary is already private".to_string()));
+        }
+
+        let mut index = vec![];
+        for (name, _) in self.entry_files()? {
+            let file_name = random_file_name();
+            fs::rename(
+                self.repo_dir().join(&name),
+                self.repo_dir().join(&file_name),
+            )?;
+            index.push((name, file_name));
+        }
+        self.write_index(&index)?;
 
-        for path in paths {
-            let file_name = path?.file_name().into_string().unwrap();
-            if !file_name.starts_with(".") {
-                file_names.push(file_name);
+        let mut changes = vec![(INDEX_FILE_NAME, Change::Add)];
+        for (name, file_name) in &index {
+            changes.push((name.as_str(), Change::Delete));
+            changes.push((file_name.as_str(), Change::Add));
+        }
+        self.commit_changes(&changes, "Enable privacy mode", false)
+    }
+
+    /// Re-encrypts every entry to `recipients` and records the new recipients, all in a single
+    /// commit. The new recipients must be of the same kind (GPG or age) as the current ones.
+    pub fn change_recipients(&self, recipients: &[&str]) -> Result<()> {
+        let age = self.recipients_path().ends_with(AGE_RECIPIENTS_FILE_NAME);
+        if recipients.is_empty() {
+            return Err(DearyError::Config(
+                "At least one recipient is required".to_string(),
+            ));
+        }
+        if recipients.iter().any(|r| Age::is_recipient(r) != age) {
+            return Err(DearyError::Config(
+                "Cannot switch between GPG and age recipients".to_string(),
+            ));
+        }
+
+        let new_recipients: Vec<String> = recipients.iter().map(|r| r.trim().to_string()).collect();
+        let mut names: Vec<String> = self.entry_files()?.into_iter().map(|(_, f)| f).collect();
+        if self.is_private() {
+            names.push(INDEX_FILE_NAME.to_string());
+        }
+        if self.has_metadata_index() {
+            names.push(METADATA_FILE_NAME.to_string());
+        }
+
+        // Re-encrypt everything in memory first, so that a failure leaves the diary untouched
+        let mut ciphertexts = vec![];
+        for name in &names {
+            let text = self.decrypt_entry(&self.repo_dir().join(name))?;
+            ciphertexts.push(self.encrypt_text(&text, &new_recipients)?);
+        }
+
+        for (name, ciphertext) in names.iter().zip(ciphertexts) {
+            File::create(self.repo_dir().join(name))?.write_all(&ciphertext)?;
+        }
+        self.write_recipients_file(&self.recipients_path(), recipients)?;
+        // The search index holds every word of every entry, and is not committed
+        self.remove_search_index()?;
+
+        let recipients_file_name = if age {
+            AGE_RECIPIENTS_FILE_NAME
+        } else {
+            GPG_ID_FILE_NAME
+        };
+        let mut changes = vec![(recipients_file_name, Change::Edit)];
+        changes.extend(names.iter().map(|n| (n.as_str(), Change::Edit)));
+        self.commit_changes(&changes, "Change recipients", false)
+    }
+
+    /// Checks the signature of every commit reachable from HEAD, newest first.
+    pub fn verify_history(&self) -> Result<Vec<CommitVerification>> {
+        let config = self.repo.config()?;
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_head()?;
+
+        let mut commits = vec![];
+        for oid in revwalk {
+            let oid = oid?;
+            let commit = self.repo.find_commit(oid)?;
+            let verification = match self.repo.extract_signature(&oid, None) {
+                Ok((signature, content)) => {
+                    signing::verify(&signature, &content, &config, self.config.tmp_dir())?
+                }
+                Err(e) if e.code() == git2::ErrorCode::NotFound => Verification::Unsigned,
+                Err(e) => return Err(DearyError::from(e)),
             };
+            commits.push(CommitVerification {
+                id: oid.to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                verification,
+            });
         }
-        Ok(file_names)
+        Ok(commits)
+    }
+
+    fn create_recipients_file(&self, file_name: &str, recipients: &[&str]) -> Result<()> {
+        self.write_recipients_file(&self.repo_dir().join(file_name), recipients)?;
+        self.commit_change(file_name, Change::Add, true)
     }
 
-    fn create_gpg_id_file(&self, gpg_id: &str) -> Result<()> {
-        let mut file = File::create(self.gpg_id_path())?;
-        file.write_all(gpg_id.as_bytes())?;
-        self.commit_change(GPG_ID_FILE_NAME, Change::Add, true)
+    fn write_recipients_file(&self, path: &Path, recipients: &[&str]) -> Result<()> {
+        let mut file = File::create(path)?;
+        for recipient in recipients {
+            writeln!(file, "{}", recipient.trim())?;
+        }
+        Ok(())
     }
 
     fn set_config(&self, config: HashMap<&str, &str>) -> Result<()> {
@@ -159,19 +347,47 @@ impl Deary {
         Ok(())
     }
 
+    /// Commits a change to an entry file, along with the metadata index. In a private diary,
+    /// the index is committed too and the commit message does not name the entry.
+    fn commit_entry(&self, file: &str, change: Change) -> Result<()> {
+        let mut changes = vec![(file, change)];
+        if self.has_metadata_index() {
+            changes.push((METADATA_FILE_NAME, Change::Edit));
+        }
+        if self.is_private() {
+            changes.push((INDEX_FILE_NAME, Change::Edit));
+            self.commit_changes(&changes, PRIVATE_COMMIT_MESSAGE, false)
+        } else {
+            self.commit_changes(&changes, &format!("{:?} {}", change, file), false)
+        }
+    }
+
     fn commit_change(&self, file: &str, change: Change, initial: bool) -> Result<()> {
-        let file_path = Path::new(file);
+        self.commit_changes(
+            &[(file, change)],
+            &format!("{:?} {}", change, file),
+            initial,
+        )
+    }
 
+    fn commit_changes(
+        &self,
+        changes: &[(&str, Change)],
+        message: &str,
+        initial: bool,
+    ) -> Result<()> {
         let mut index = self.repo.index()?;
-        match change {
-            Change::Delete => index.remove_path(file_path)?,
-            _ => index.add_path(file_path)?,
+        for (file, change) in changes {
+            let file_path = Path::new(file);
+            match change {
+                Change::Delete => index.remove_path(file_path)?,
+                _ => index.add_path(file_path)?,
+            }
         }
         index.write()?;
 
         let oid = index.write_tree()?;
         let tree = self.repo.find_tree(oid)?;
-        let signature = self.repo.signature()?;
 
         let mut parent_commit: Vec<&git2::Commit> = vec![];
 
@@ -182,15 +398,76 @@ impl Deary {
             parent_commit.push(&commit);
         }
 
-        self.repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            &format!("{:?} {}", change, file),
-            &tree,
-            &parent_commit,
-        )?;
+        self.commit_tree(&tree, &parent_commit, message)?;
+        Ok(())
+    }
+
+    /// Commits `tree` on top of `parents` and moves HEAD to the new commit.
+    fn commit_tree(
+        &self,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+        message: &str,
+    ) -> Result<git2::Oid> {
+        let signature = self.signature()?;
+        let oid = self.write_commit(&signature, &signature, message, tree, parents)?;
+        self.update_head(oid, message)?;
+        Ok(oid)
+    }
 
+    /// The identity to commit with: the configured one, or else the one in the repository's
+    /// git config.
+    fn signature(&self) -> Result<git2::Signature<'static>> {
+        let signature = self.repo.signature()?;
+        let name = match &self.config.git_name {
+            Some(name) => name.as_str(),
+            None => signature.name().unwrap_or_default(),
+        };
+        let email = match &self.config.git_email {
+            Some(email) => email.as_str(),
+            None => signature.email().unwrap_or_default(),
+        };
+        Ok(git2::Signature::now(name, email)?)
+    }
+
+    /// Writes a commit without updating any reference, signing it if the diary is configured
+    /// to.
+    fn write_commit(
+        &self,
+        author: &git2::Signature,
+        committer: &git2::Signature,
+        message: &str,
+        tree: &git2::Tree,
+        parents: &[&git2::Commit],
+    ) -> Result<git2::Oid> {
+        match CommitSigner::from_config(&self.repo.config()?)? {
+            Some(signer) => {
+                let content = self
+                    .repo
+                    .commit_create_buffer(author, committer, message, tree, parents)?;
+                let content = content.as_str().unwrap();
+                Ok(self.repo.commit_signed(
+                    content,
+                    &signer.sign(content, self.config.tmp_dir())?,
+                    None,
+                )?)
+            }
+            None => Ok(self
+                .repo
+                .commit(None, author, committer, message, tree, parents)?),
+        }
+    }
+
+    /// Points HEAD (or the branch it refers to) at `oid`.
+    fn update_head(&self, oid: git2::Oid, message: &str) -> Result<()> {
+        let head = self.repo.find_reference("HEAD")?;
+        let log_message = format!("commit: {}", message);
+        match head.symbolic_target() {
+            Some(branch) => {
+                self.repo.reference(branch, oid, true, &log_message)?;
+            }
+            None => self.repo.set_head_detached(oid)?,
+        }
         Ok(())
     }
 
@@ -198,29 +475,311 @@ impl Deary {
         self.repo.workdir().unwrap()
     }
 
-    fn gpg_id_path(&self) -> PathBuf {
-        self.repo_dir().join(GPG_ID_FILE_NAME)
+    /// Names a new entry after the current time, see [`Config::entry_name_format`].
+    fn new_entry_name(&self) -> Result<String> {
+        let format = match &self.config.entry_name_format {
+            Some(format) => format.as_str(),
+            None => ENTRY_NAME_FORMAT,
+        };
+        let name = Utc::now().format(format).to_string();
+        // Checked before opening the editor, so that no text is lost
+        if self.list_entries()?.contains(&name) {
+            return Err(DearyError::EntryExists(name));
+        }
+        Ok(name)
+    }
+
+    /// Creates a temporary file for decrypted text, see [`Config::tmp_dir`].
+    fn tmp_file(&self) -> Result<NamedTempFile> {
+        Ok(NamedTempFile::new_in(self.config.tmp_dir())?)
     }
 
-    fn gpg_id(&self) -> Result<String> {
-        let mut file = File::open(self.gpg_id_path())?;
-        let mut gpg_id = String::new();
-        file.read_to_string(&mut gpg_id)?;
-        Ok(gpg_id)
+    /// Opens `temp_file_path` in the configured editor, or in `EDITOR`, which defaults to vim.
+    fn open_editor(&self, temp_file_path: &Path) -> Result<()> {
+        let editor = match &self.config.editor {
+            Some(editor) => PathBuf::from(editor),
+            None => find_editor()?,
+        };
+
+        let status = Command::new(&editor)
+            .args(&self.config.editor_args)
+            .arg(temp_file_path)
+            .spawn()
+            .and_then(|mut child| child.wait())
+            .map_err(|e| DearyError::Editor(format!("{}: {}", editor.display(), e)))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(DearyError::Editor(format!("{}", status)))
+        }
+    }
+
+    /// Returns the path of an existing entry. Hidden files, such as `.gpg_id`, are not entries.
+    fn entry_path(&self, name: &str) -> Result<PathBuf> {
+        Ok(self.repo_dir().join(self.entry_file(name)?))
+    }
+
+    /// Returns the name of the file an existing entry is stored in.
+    fn entry_file(&self, name: &str) -> Result<String> {
+        let not_found = || DearyError::EntryNotFound(name.to_string());
+        if name.starts_with('.') || name.contains('/') {
+            return Err(not_found());
+        }
+
+        let file_name = if self.is_private() {
+            match self.read_index()?.into_iter().find(|(n, _)| n == name) {
+                Some((_, file_name)) => file_name,
+                None => return Err(not_found()),
+            }
+        } else {
+            name.to_string()
+        };
+        if !self.repo_dir().join(&file_name).is_file() {
+            return Err(not_found());
+        }
+        Ok(file_name)
+    }
+
+    /// Lists entries as pairs of entry name and file name, which only differ in a private
+    /// diary, sorted by name.
+    fn entry_files(&self) -> Result<Vec<(String, String)>> {
+        let mut entries = vec![];
+        if self.is_private() {
+            entries = self.read_index()?;
+        } else {
+            for path in read_dir(self.repo_dir())? {
+                let file_name = path?.file_name().into_string().unwrap();
+                if !file_name.starts_with('.') {
+                    entries.push((file_name.clone(), file_name));
+                };
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Reads the encrypted index of a private diary, see [`parse_index`].
+    fn read_index(&self) -> Result<Vec<(String, String)>> {
+        let text = self.decrypt_entry(&self.repo_dir().join(INDEX_FILE_NAME))?;
+        Ok(parse_index(&text))
+    }
+
+    fn write_index(&self, index: &[(String, String)]) -> Result<()> {
+        let ciphertext = self.encrypt_text(&format_index(index), &self.recipients()?)?;
+        File::create(self.repo_dir().join(INDEX_FILE_NAME))?.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    fn recipients_path(&self) -> PathBuf {
+        let age_path = self.repo_dir().join(AGE_RECIPIENTS_FILE_NAME);
+        if age_path.exists() {
+            age_path
+        } else {
+            self.repo_dir().join(GPG_ID_FILE_NAME)
+        }
+    }
+
+    /// Reads the recipients file: one recipient per line, like `.gpg-id` in `pass`.
+    pub fn recipients(&self) -> Result<Vec<String>> {
+        let mut file = File::open(self.recipients_path())?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(parse_recipients(&contents))
+    }
+
+    fn decrypt_entry(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut ciphertext = vec![];
+        File::open(path)?.read_to_end(&mut ciphertext)?;
+        self.cipher.decrypt(&ciphertext)
+    }
+
+    fn encrypt_entry(&self, input_path: &Path, output_path: &Path) -> Result<()> {
+        let mut plaintext = vec![];
+        File::open(input_path)?.read_to_end(&mut plaintext)?;
+        let ciphertext = self.encrypt_text(&plaintext, &self.recipients()?)?;
+        File::create(output_path)?.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    /// Encrypts `plaintext`, signing it first if the diary has a signing key configured.
+    fn encrypt_text(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        match self.signing_key()? {
+            Some(signer) => self.cipher.sign_and_encrypt(plaintext, recipients, &signer),
+            None => self.cipher.encrypt(plaintext, recipients),
+        }
+    }
+
+    /// The key entries are signed with, set in the repository's git config as
+    /// `deary.signingKey`.
+    fn signing_key(&self) -> Result<Option<String>> {
+        match self.repo.config()?.get_string(SIGNING_KEY_CONFIG) {
+            Ok(key) => Ok(Some(key)),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(DearyError::from(e)),
+        }
+    }
+}
+
+/// The path of the diary to use: the one named `diary`, or else the one `DEARY_DIR` points to,
+/// or else the one named in the user's settings, or else the default diary, see
+/// [`list_diaries`].
+pub fn find_repo_path(diary: Option<&str>) -> Result<PathBuf> {
+    let config = Config::load(None)?;
+    if diary.is_none() {
+        match env::var_os(DIARY_DIR_ENV) {
+            Some(dir) if !dir.is_empty() => return Ok(PathBuf::from(dir)),
+            _ => {}
+        }
+    }
+    match diary.or(config.diary.as_deref()) {
+        Some(name) => diary_path(&config, name),
+        None => Ok(default_repo_path(&config)),
     }
 }
 
-pub fn find_repo_path() -> PathBuf {
-    let home = env::var("HOME").unwrap();
-    let mut path = PathBuf::from(home);
-    path.push(REPO_DIR);
-    path
+/// Lists diaries as pairs of name and path: the default one, at `path` from the user's settings
+/// or in `~/.deary`, if it exists, then those in the user's settings and in `~/.deary.d`,
+/// sorted by name.
+pub fn list_diaries() -> Result<Vec<(String, PathBuf)>> {
+    let config = Config::load(None)?;
+    let mut diaries = config.diaries.clone();
+    match read_dir(home_dir().join(DIARIES_DIR)) {
+        Ok(dirs) => {
+            for dir in dirs {
+                let dir = dir?;
+                let name = dir.file_name().to_string_lossy().into_owned();
+                if !name.starts_with('.') && dir.path().is_dir() {
+                    diaries.entry(name).or_insert_with(|| dir.path());
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(DearyError::from(e)),
+    }
+
+    let mut list = vec![];
+    let default_path = default_repo_path(&config);
+    if default_path.exists() {
+        list.push((DEFAULT_DIARY.to_string(), default_path));
+    }
+    list.extend(
+        diaries
+            .into_iter()
+            .filter(|(name, _)| name != DEFAULT_DIARY),
+    );
+    Ok(list)
 }
 
-fn find_executable(name: &str) -> Result<PathBuf> {
+/// The path of the diary named `name`, which is in `~/.deary.d` unless the user's settings put
+/// it elsewhere. The diary named `default` is the default one.
+fn diary_path(config: &Config, name: &str) -> Result<PathBuf> {
+    if name == DEFAULT_DIARY {
+        return Ok(default_repo_path(config));
+    }
+    if let Some(path) = config.diaries.get(name) {
+        return Ok(path.clone());
+    }
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(DearyError::Config(format!("Invalid diary name {}", name)));
+    }
+    Ok(home_dir().join(DIARIES_DIR).join(name))
+}
+
+fn default_repo_path(config: &Config) -> PathBuf {
+    match &config.path {
+        Some(path) => path.clone(),
+        None => home_dir().join(REPO_DIR),
+    }
+}
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap())
+}
+
+/// Parses the index of a private diary: one entry name and file name per line, separated by a
+/// tab.
+fn parse_index(text: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(text)
+        .lines()
+        .filter_map(|l| l.split_once('\t'))
+        .map(|(name, file_name)| (name.to_string(), file_name.to_string()))
+        .collect()
+}
+
+fn format_index(index: &[(String, String)]) -> Vec<u8> {
+    let mut text = String::new();
+    for (name, file_name) in index {
+        text.push_str(&format!("{}\t{}\n", name, file_name));
+    }
+    text.into_bytes()
+}
+
+fn parse_recipients(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// A random name for an entry file in a private diary, which tells nothing about the entry.
+fn random_file_name() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Uses the native OpenPGP backend if `DEARY_KEYRING` points to a keyring file, and the `gpg`
+/// executable otherwise.
+fn default_cipher() -> Result<Box<dyn Cipher>> {
+    match env::var_os(KEYRING_ENV) {
+        Some(path) => Ok(Box::new(OpenPgp::from_keyring(Path::new(&path))?)),
+        None => Ok(Box::new(Gpg)),
+    }
+}
+
+/// Reads age identities from the files listed in `DEARY_AGE_IDENTITY`, falling back to the
+/// user's SSH keys.
+fn age_cipher() -> Box<dyn Cipher> {
+    let identity_files = match env::var_os(AGE_IDENTITY_ENV) {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => {
+            let home = PathBuf::from(env::var_os("HOME").unwrap_or_default());
+            DEFAULT_AGE_IDENTITIES
+                .iter()
+                .map(|p| home.join(p))
+                .collect()
+        }
+    };
+    Box::new(Age::new(identity_files))
+}
+
+/// Runs `command`, feeding it `input` on stdin and capturing its stdout and stderr.
+pub(crate) fn run_with_input(command: &mut Command, input: &[u8]) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Feed stdin from a separate thread, otherwise the child may block on a full stdout pipe
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    match writer.join().unwrap() {
+        // The child closing its stdin early is reported through its exit status instead
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(DearyError::from(e)),
+        _ => Ok(output),
+    }
+}
+
+pub(crate) fn find_executable(name: &str) -> Result<PathBuf> {
     match which::which(name) {
         Ok(e) => Ok(e),
-        Err(error) => Err(DearyError::new(&format!(
+        Err(error) => Err(DearyError::Config(format!(
             "{} not found in PATH ({})",
             name, error
         ))),
@@ -232,7 +791,7 @@ fn find_editor() -> Result<PathBuf> {
         Ok(editor) => Ok(PathBuf::from(editor)),
         Err(_) => match find_executable(EDITOR) {
             Ok(vim) => Ok(vim),
-            Err(error) => Err(DearyError::new(&format!(
+            Err(error) => Err(DearyError::Editor(format!(
                 "EDITOR not set, default is not usable: {}",
                 &error
             ))),
@@ -240,55 +799,49 @@ fn find_editor() -> Result<PathBuf> {
     }
 }
 
-fn find_gpg() -> Result<PathBuf> {
-    find_executable(GPG)
-}
-
-fn open_editor(temp_file_path: &Path) -> Result<()> {
-    let editor = match find_editor() {
-        Ok(e) => e,
-        Err(err) => return Err(err),
-    };
-
-    let status = Command::new(&editor).arg(temp_file_path).spawn()?.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(DearyError::new(&format!("{}", status)))
+#[cfg(test)]
+mod tests {
+    use crate::testing::{mock_diary, mock_entry, mock_recipients};
+    use crate::{SearchOptions, Verification};
+    use std::fs;
+
+    #[test]
+    fn entries_round_trip_through_a_custom_cipher() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deary = mock_diary(dir.path(), &["alice"]);
+        let first = mock_entry(&mut deary, "first entry");
+        let second = mock_entry(&mut deary, "second entry");
+
+        assert_eq!(deary.list_entries().unwrap(), vec![first.clone(), second]);
+        let (text, verification) = deary.read_entry(&first).unwrap();
+        assert_eq!(text, b"first entry");
+        assert_eq!(verification, Verification::Unsigned);
+
+        let ciphertext = fs::read(dir.path().join(&first)).unwrap();
+        assert_eq!(mock_recipients(&ciphertext), vec!["alice"]);
     }
-}
 
-fn decrypt_entry(path: &Path) -> Result<Vec<u8>> {
-    let gpg = match find_gpg() {
-        Ok(g) => g,
-        Err(err) => return Err(err),
-    };
-    Ok(Command::new(gpg)
-        .args(GPG_OPTS)
-        .arg("--decrypt")
-        .arg(path)
-        .output()?
-        .stdout)
-}
-
-fn encrypt_entry(input_path: &Path, output_path: &Path, gpg_id: &str) -> Result<()> {
-    let gpg = match find_gpg() {
-        Ok(g) => g,
-        Err(err) => return Err(err),
-    };
-    let status = Command::new(gpg)
-        .args(GPG_OPTS)
-        .arg("--encrypt")
-        .arg("--recipient")
-        .arg(gpg_id.trim())
-        .arg("--output")
-        .arg(output_path)
-        .arg(input_path)
-        .spawn()?
-        .wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(DearyError::new(&format!("{}", status)))
+    #[test]
+    fn changing_recipients_rebuilds_the_search_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deary = mock_diary(dir.path(), &["alice"]);
+        let name = mock_entry(&mut deary, "secret words");
+        let search_index = dir.path().join(".git/deary/search_index");
+        let options = SearchOptions::default();
+        assert_eq!(deary.search("words", options).unwrap().len(), 1);
+        assert_eq!(
+            mock_recipients(&fs::read(&search_index).unwrap()),
+            vec!["alice"]
+        );
+
+        deary.change_recipients(&["bob"]).unwrap();
+        assert!(!search_index.exists());
+        let hits = deary.search("words", options).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, name);
+        assert_eq!(
+            mock_recipients(&fs::read(&search_index).unwrap()),
+            vec!["bob"]
+        );
     }
 }
@@ -1,15 +1,100 @@
-mod lib;
-
-use clap::{crate_authors, crate_description, crate_version, App, Arg};
-use lib::{find_repo_path, Deary, DearyError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{crate_authors, crate_description, crate_version, App, AppSettings, Arg};
+use deary::{
+    find_repo_path, is_ssh_key, list_diaries, Config, Deary, DearyError, DiffStyle, EntryInfo,
+    Metadata, Revision, SearchOptions, Verification, CONFIG_KEYS,
+};
+use serde_json::json;
 use std::collections::HashMap;
 use std::env;
 use std::io;
 use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
-fn exit_with_error(error: DearyError) {
+fn exit_with_error(error: DearyError) -> ! {
     eprintln!("{}", error);
-    std::process::exit(1);
+    std::process::exit(error.exit_code());
+}
+
+/// The argument naming an existing entry, which can also be given by date, see
+/// [`Deary::find_entry`].
+fn entry_arg() -> Arg<'static> {
+    Arg::with_name("name")
+        .about("Entry name, latest, -N for the Nth newest entry, or a date, e.g. today")
+        .required(true)
+        .allow_hyphen_values(true)
+}
+
+fn find_entry(deary: &Deary, expr: &str) -> String {
+    deary
+        .find_entry(expr)
+        .unwrap_or_else(|e| exit_with_error(e))
+}
+
+/// The argument choosing between plain and machine-readable output.
+fn format_arg() -> Arg<'static> {
+    Arg::with_name("format")
+        .about("Output format; ndjson prints one JSON object per line")
+        .long("format")
+        .takes_value(true)
+        .possible_values(&["text", "json", "ndjson"])
+        .default_value("text")
+}
+
+fn json_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn entry_json(
+    entry: &EntryInfo,
+    revision: Option<&Revision>,
+    body: Option<&[u8]>,
+) -> serde_json::Value {
+    let mut json = json!({
+        "name": entry.name,
+        "created": entry.created.map(json_time),
+        "size": entry.size,
+        "commit": revision.map(|r| json!({
+            "id": r.id,
+            "time": json_time(r.time),
+            "summary": r.summary,
+        })),
+        "title": entry.metadata.title,
+        "tags": entry.metadata.tags,
+        "mood": entry.metadata.mood,
+        "location": entry.metadata.location,
+    });
+    if let Some(body) = body {
+        json["body"] = json!(String::from_utf8_lossy(body));
+    }
+    json
+}
+
+/// Prints `values` as a JSON array, or as one JSON object per line for ndjson.
+fn print_json(values: Vec<serde_json::Value>, format: &str) {
+    if format == "ndjson" {
+        for value in values {
+            println!("{}", value);
+        }
+    } else {
+        println!("{}", serde_json::Value::Array(values));
+    }
+}
+
+fn repo_path(diary: Option<&str>) -> PathBuf {
+    find_repo_path(diary).unwrap_or_else(|e| exit_with_error(e))
+}
+
+/// The git identity a new diary, or a new clone of one, commits with.
+fn git_identity(config: &Config) -> HashMap<&str, &str> {
+    let mut git_config = HashMap::new();
+    git_config.insert("user.name", config.git_name.as_deref().unwrap_or("noname"));
+    git_config.insert(
+        "user.email",
+        config.git_email.as_deref().unwrap_or("noemail"),
+    );
+    git_config
 }
 
 fn main() {
@@ -17,52 +102,383 @@ fn main() {
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        // For entries given as -N, see `entry_arg`
+        .global_setting(AppSettings::AllowNegativeNumbers)
+        .arg(
+            Arg::with_name("diary")
+                .about("Use the diary with this name instead of the default one")
+                .long("diary")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            App::new("init")
+                .about("Initialize a new diary")
+                .arg(
+                    Arg::with_name("key_id")
+                        .about(
+                            "GPG key IDs (or email addresses, associated with the keys), \
+                             or age or SSH public keys",
+                        )
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("signing_key")
+                        .about("Sign every entry with this GPG key")
+                        .long("signing-key")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("sign_commits")
+                        .about("Sign every commit with this GPG key ID or SSH key")
+                        .long("sign-commits")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("private")
+                        .about("Give entry files random names and keep commit messages generic")
+                        .long("private"),
+                ),
+        )
+        .subcommand(
+            App::new("clone")
+                .about("Clone an existing diary from a git remote")
+                .arg(
+                    Arg::with_name("url")
+                        .about("Remote URL or path")
+                        .required(true),
+                ),
+        )
         .subcommand(
-            App::new("init").about("Initialize a new diary").arg(
-                Arg::with_name("key_id")
-                    .about("GPG key ID (or email address, associated with the key)")
-                    .required(true),
-            ),
+            App::new("list")
+                .about("List diary entries")
+                .arg(
+                    Arg::with_name("deleted")
+                        .about("Also list deleted entries, separately")
+                        .long("deleted"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .about("Only list entries with this tag, or without it if prefixed with -")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .allow_hyphen_values(true),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .about("Only list entries created on or after this date")
+                        .long("since")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .about("Only list entries created on or before this date")
+                        .long("until")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("on")
+                        .about("Only list entries created on this date")
+                        .long("on")
+                        .takes_value(true)
+                        .conflicts_with_all(&["since", "until"]),
+                )
+                .arg(
+                    Arg::with_name("reverse")
+                        .about("List the newest entries first")
+                        .long("reverse"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .about("List at most this many entries")
+                        .long("limit")
+                        .takes_value(true),
+                )
+                .arg(format_arg())
+                .arg(
+                    Arg::with_name("body")
+                        .about("Include the text of every entry in JSON output")
+                        .long("body"),
+                ),
         )
-        .subcommand(App::new("list").about("List diary entries"))
         .subcommand(
             App::new("show")
                 .about("Show a diary entry")
-                .arg(Arg::with_name("name").about("Entry name").required(true)),
+                .arg(entry_arg())
+                .arg(
+                    Arg::with_name("verify")
+                        .about("Fail unless the entry has a good signature from a trusted key")
+                        .long("verify"),
+                )
+                .arg(
+                    Arg::with_name("at")
+                        .about("Show the entry as it was at this revision")
+                        .long("at")
+                        .takes_value(true),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            App::new("search")
+                .about("Search the text of diary entries")
+                .arg(
+                    Arg::with_name("pattern")
+                        .about("Text to search for")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("regex")
+                        .about("Treat the pattern as a regular expression")
+                        .long("regex")
+                        .short('E'),
+                )
+                .arg(
+                    Arg::with_name("ignore_case")
+                        .about("Ignore case")
+                        .long("ignore-case")
+                        .short('i'),
+                )
+                .arg(
+                    Arg::with_name("context")
+                        .about("Lines of context to show around matches")
+                        .long("context")
+                        .short('C')
+                        .takes_value(true)
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            App::new("history")
+                .about("List the revisions of a diary entry")
+                .arg(entry_arg()),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Show changes to a diary entry between two revisions")
+                .arg(entry_arg())
+                .arg(
+                    Arg::with_name("from").about(
+                        "Revision to compare from (default: the one before the last change)",
+                    ),
+                )
+                .arg(Arg::with_name("to").about("Revision to compare to (default: current)"))
+                .arg(
+                    Arg::with_name("words")
+                        .about("Show changed words instead of changed lines")
+                        .long("words"),
+                )
+                .arg(
+                    Arg::with_name("color")
+                        .about("When to color the diff")
+                        .long("color")
+                        .takes_value(true)
+                        .possible_values(&["auto", "always", "never"])
+                        .default_value("auto"),
+                ),
+        )
+        .subcommand(
+            App::new("restore")
+                .about("Restore a diary entry to a past revision")
+                .arg(entry_arg())
+                .arg(Arg::with_name("rev").about("Revision").required(true)),
         )
         .subcommand(App::new("create").about("Create a new diary entry"))
         .subcommand(
             App::new("edit")
                 .about("Edit a diary entry")
-                .arg(Arg::with_name("name").about("Entry name").required(true)),
+                .arg(entry_arg()),
         )
         .subcommand(
             App::new("delete")
                 .about("Delete a diary entry")
+                .arg(entry_arg()),
+        )
+        .subcommand(App::new("trash").about("List deleted diary entries"))
+        .subcommand(
+            App::new("undelete")
+                .about("Bring back a deleted diary entry")
                 .arg(Arg::with_name("name").about("Entry name").required(true)),
         )
+        .subcommand(
+            App::new("purge")
+                .about("Remove a diary entry from the whole history")
+                .arg(Arg::with_name("name").about("Entry name").required(true)),
+        )
+        .subcommand(
+            App::new("tag")
+                .about("Manage the tags of a diary entry")
+                .subcommand(
+                    App::new("add")
+                        .about("Add tags to a diary entry")
+                        .arg(entry_arg())
+                        .arg(
+                            Arg::with_name("tag")
+                                .about("Tags")
+                                .multiple(true)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    App::new("remove")
+                        .about("Remove tags from a diary entry")
+                        .arg(entry_arg())
+                        .arg(
+                            Arg::with_name("tag")
+                                .about("Tags")
+                                .multiple(true)
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(App::new("tags").about("List tags, with the number of entries having each"))
+        .subcommand(App::new("reindex").about("Rebuild the index of entry titles and tags"))
+        .subcommand(
+            App::new("reencrypt")
+                .about("Re-encrypt all diary entries to new recipients")
+                .arg(
+                    Arg::with_name("key_id")
+                        .about(
+                            "GPG key IDs (or email addresses, associated with the keys), \
+                             or age or SSH public keys",
+                        )
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("git")
+                .about("Manage the diary's git repository")
+                .subcommand(
+                    App::new("remote")
+                        .about("List the diary's git remotes")
+                        .subcommand(
+                            App::new("add")
+                                .about("Add a git remote")
+                                .arg(Arg::with_name("name").about("Remote name").required(true))
+                                .arg(
+                                    Arg::with_name("url")
+                                        .about("Remote URL or path")
+                                        .required(true),
+                                ),
+                        )
+                        .subcommand(
+                            App::new("remove")
+                                .about("Remove a git remote")
+                                .arg(Arg::with_name("name").about("Remote name").required(true)),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("push")
+                .about("Push the diary to a git remote")
+                .arg(Arg::with_name("remote").about("Remote name (default: origin)"))
+                .arg(
+                    Arg::with_name("force")
+                        .about("Overwrite the remote history, e.g. after purging an entry")
+                        .long("force"),
+                ),
+        )
+        .subcommand(
+            App::new("pull")
+                .about("Pull the diary from a git remote, merging if needed")
+                .arg(Arg::with_name("remote").about("Remote name (default: origin)")),
+        )
+        .subcommand(
+            App::new("make-private")
+                .about("Rename entry files to random names and keep commit messages generic"),
+        )
+        .subcommand(
+            App::new("verify-history")
+                .about("Report commits that are unsigned or not signed by a trusted key"),
+        )
+        .subcommand(App::new("diaries").about("List diaries, marking the one in use"))
+        .subcommand(
+            App::new("config")
+                .about("Read or change settings")
+                .subcommand(
+                    App::new("get")
+                        .about("Print a setting, or every setting that is set")
+                        .arg(
+                            Arg::with_name("key")
+                                .about("Setting")
+                                .possible_values(CONFIG_KEYS),
+                        ),
+                )
+                .subcommand(
+                    App::new("set")
+                        .about("Change a setting in the user's settings")
+                        // For editor.args, which may start with a hyphen
+                        .setting(AppSettings::AllowLeadingHyphen)
+                        .arg(
+                            Arg::with_name("key")
+                                .about("Setting")
+                                .required(true)
+                                .possible_values(CONFIG_KEYS),
+                        )
+                        .arg(
+                            Arg::with_name("value")
+                                .about("Value; editor.args takes any number of them")
+                                .multiple(true)
+                                .required(true)
+                                .allow_hyphen_values(true),
+                        )
+                        .arg(
+                            Arg::with_name("repo")
+                                .about("Change the diary's settings instead, and commit them")
+                                .long("repo"),
+                        ),
+                ),
+        )
         .get_matches();
 
+    let diary = deary.value_of("diary");
     match deary.subcommand() {
         ("init", Some(init)) => {
-            let repo_path = find_repo_path();
+            let repo_path = repo_path(diary);
+            if repo_path.exists() {
+                exit_with_error(DearyError::RepoExists(repo_path));
+            }
+
+            let config = Config::load(None).unwrap_or_else(|e| exit_with_error(e));
+            let mut git_config = git_identity(&config);
+            if let Some(key) = init.value_of("signing_key") {
+                git_config.insert("deary.signingKey", key);
+            }
+            if let Some(key) = init.value_of("sign_commits") {
+                git_config.insert("commit.gpgSign", "true");
+                git_config.insert("user.signingKey", key);
+                if is_ssh_key(key) {
+                    git_config.insert("gpg.format", "ssh");
+                }
+            }
+            let recipients: Vec<&str> = init.values_of("key_id").unwrap().collect();
+            if let Err(e) = Deary::init(
+                &repo_path,
+                &recipients,
+                git_config,
+                init.is_present("private"),
+            ) {
+                exit_with_error(e);
+            }
+        }
+        ("clone", Some(clone)) => {
+            let repo_path = repo_path(diary);
             if repo_path.exists() {
-                exit_with_error(DearyError::new(&format!(
-                    "Repository {} already exists",
-                    repo_path.display()
-                )));
-                std::process::exit(1);
+                exit_with_error(DearyError::RepoExists(repo_path));
             }
 
-            let mut git_config = HashMap::new();
-            git_config.insert("user.name", "noname");
-            git_config.insert("user.email", "noemail");
-            if let Err(e) = Deary::init(&repo_path, init.value_of("key_id").unwrap(), git_config) {
+            let config = Config::load(None).unwrap_or_else(|e| exit_with_error(e));
+            let git_config = git_identity(&config);
+            if let Err(e) =
+                Deary::clone_remote(clone.value_of("url").unwrap(), &repo_path, git_config)
+            {
                 exit_with_error(e);
             }
         }
         ("create", Some(_)) => {
-            match Deary::new(&find_repo_path()) {
+            match Deary::new(&repo_path(diary)) {
                 Ok(deary) => {
                     if let Err(e) = deary.create_entry() {
                         exit_with_error(e);
@@ -72,11 +488,108 @@ fn main() {
             };
         }
         ("show", Some(show)) => {
-            match Deary::new(&find_repo_path()) {
-                Ok(deary) => match deary.read_entry(show.value_of("name").unwrap()) {
-                    Ok(text) => {
-                        if let Err(e) = io::stdout().write_all(&text) {
-                            exit_with_error(DearyError::from(e))
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    let name = &find_entry(&deary, show.value_of("name").unwrap());
+                    let entry = match show.value_of("at") {
+                        Some(rev) => deary.read_entry_at(name, rev),
+                        None => deary.read_entry(name),
+                    };
+                    match entry {
+                        Ok((text, verification)) => {
+                            let trusted =
+                                matches!(verification, Verification::Good { trusted: true, .. });
+                            if show.is_present("verify") && !trusted {
+                                exit_with_error(DearyError::Verification(verification.to_string()));
+                            }
+                            let format = show.value_of("format").unwrap();
+                            if format != "text" {
+                                let created = match deary.list_entries_with_metadata() {
+                                    Ok(entries) => entries
+                                        .into_iter()
+                                        .find(|e| e.name == *name)
+                                        .and_then(|e| e.created),
+                                    Err(e) => exit_with_error(e),
+                                };
+                                let entry = EntryInfo {
+                                    name: name.to_string(),
+                                    created,
+                                    size: Some(text.len() as u64),
+                                    metadata: Metadata::parse(&text),
+                                };
+                                let revision = match deary.last_revision(name, show.value_of("at"))
+                                {
+                                    Ok(revision) => revision,
+                                    Err(e) => exit_with_error(e),
+                                };
+                                let mut json = entry_json(&entry, revision.as_ref(), Some(&text));
+                                json["signature"] = match verification {
+                                    Verification::Unsigned => serde_json::Value::Null,
+                                    _ => json!(verification.to_string()),
+                                };
+                                println!("{}", json);
+                                return;
+                            }
+
+                            if let Err(e) = io::stdout().write_all(&text) {
+                                exit_with_error(DearyError::from(e))
+                            }
+                            if verification != Verification::Unsigned {
+                                eprintln!("{}", verification);
+                            }
+                        }
+                        Err(e) => exit_with_error(e),
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("search", Some(search)) => {
+            let context = match search.value_of("context").unwrap().parse() {
+                Ok(context) => context,
+                Err(_) => exit_with_error(DearyError::InvalidInput(
+                    "Context must be a number of lines".to_string(),
+                )),
+            };
+            let options = SearchOptions {
+                regex: search.is_present("regex"),
+                ignore_case: search.is_present("ignore_case"),
+                context,
+            };
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary.search(search.value_of("pattern").unwrap(), options) {
+                    Ok(hits) => {
+                        for (i, hit) in hits.iter().enumerate() {
+                            if context > 0 && i > 0 {
+                                println!("--");
+                            }
+                            for line in &hit.lines {
+                                let separator = if line.matched { ':' } else { '-' };
+                                println!(
+                                    "{}{}{}{}{}",
+                                    hit.name, separator, line.number, separator, line.text
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => exit_with_error(e),
+                },
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("history", Some(history)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary
+                    .entry_history(&find_entry(&deary, history.value_of("name").unwrap()))
+                {
+                    Ok(revisions) => {
+                        for r in revisions {
+                            println!(
+                                "{} {} {}",
+                                &r.id[..7],
+                                deary.local_time(r.time).format("%Y-%m-%d %H:%M:%S"),
+                                r.summary
+                            );
                         }
                     }
                     Err(e) => exit_with_error(e),
@@ -84,10 +597,49 @@ fn main() {
                 Err(e) => exit_with_error(e),
             };
         }
+        ("diff", Some(diff)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    let style = DiffStyle {
+                        words: diff.is_present("words"),
+                        color: match diff.value_of("color") {
+                            Some("always") => true,
+                            Some("never") => false,
+                            _ => io::stdout().is_terminal(),
+                        },
+                    };
+                    match deary.diff_entry(
+                        &find_entry(&deary, diff.value_of("name").unwrap()),
+                        diff.value_of("from"),
+                        diff.value_of("to"),
+                        style,
+                    ) {
+                        Ok(output) => print!("{}", output),
+                        Err(e) => exit_with_error(e),
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("restore", Some(restore)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.restore_entry(
+                        &find_entry(&deary, restore.value_of("name").unwrap()),
+                        restore.value_of("rev").unwrap(),
+                    ) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
         ("edit", Some(edit)) => {
-            match Deary::new(&find_repo_path()) {
+            match Deary::new(&repo_path(diary)) {
                 Ok(deary) => {
-                    if let Err(e) = deary.update_entry(edit.value_of("name").unwrap()) {
+                    if let Err(e) =
+                        deary.update_entry(&find_entry(&deary, edit.value_of("name").unwrap()))
+                    {
                         exit_with_error(e);
                     }
                 }
@@ -95,21 +647,110 @@ fn main() {
             };
         }
         ("delete", Some(delete)) => {
-            match Deary::new(&find_repo_path()) {
+            match Deary::new(&repo_path(diary)) {
                 Ok(deary) => {
-                    if let Err(e) = deary.delete_entry(delete.value_of("name").unwrap()) {
+                    if let Err(e) =
+                        deary.delete_entry(&find_entry(&deary, delete.value_of("name").unwrap()))
+                    {
                         exit_with_error(e);
                     }
                 }
                 Err(e) => exit_with_error(e),
             };
         }
-        ("list", Some(_)) => {
-            match Deary::new(&find_repo_path()) {
-                Ok(deary) => match deary.list_entries() {
-                    Ok(entries) => {
+        ("list", Some(list)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary.list_entries_with_metadata() {
+                    Ok(mut entries) => {
+                        let filter: Vec<&str> = list.values_of("tag").unwrap_or_default().collect();
+                        entries.retain(|e| e.metadata.matches_tags(&filter));
+
+                        let period = |arg| match list.value_of(arg).map(|d| deary.parse_period(d)) {
+                            Some(Ok(period)) => Some(period),
+                            Some(Err(e)) => exit_with_error(e),
+                            None => None,
+                        };
+                        let (since, until) = match period("on") {
+                            Some(on) => (Some(on), Some(on)),
+                            None => (period("since"), period("until")),
+                        };
+                        entries.retain(|e| e.created_between(since, until));
+
+                        if list.is_present("reverse") {
+                            entries.reverse();
+                        }
+                        if let Some(limit) = list.value_of("limit") {
+                            match limit.parse() {
+                                Ok(limit) => entries.truncate(limit),
+                                Err(_) => exit_with_error(DearyError::InvalidInput(
+                                    "Limit must be a number of entries".to_string(),
+                                )),
+                            }
+                        }
+                        let format = list.value_of("format").unwrap();
+                        if format != "text" {
+                            let revisions = match deary.last_revisions() {
+                                Ok(revisions) => revisions,
+                                Err(e) => exit_with_error(e),
+                            };
+                            let mut values = vec![];
+                            for e in &entries {
+                                let body = if list.is_present("body") {
+                                    match deary.read_entry(&e.name) {
+                                        Ok((text, _)) => Some(text),
+                                        Err(e) => exit_with_error(e),
+                                    }
+                                } else {
+                                    None
+                                };
+                                let mut json =
+                                    entry_json(e, revisions.get(&e.name), body.as_deref());
+                                json["deleted"] = serde_json::Value::Null;
+                                values.push(json);
+                            }
+                            if list.is_present("deleted") {
+                                let deleted = match deary.deleted_entries() {
+                                    Ok(deleted) => deleted,
+                                    Err(e) => exit_with_error(e),
+                                };
+                                for d in deleted {
+                                    let entry = match deary.deleted_entry_info(&d) {
+                                        Ok(entry) => entry,
+                                        Err(e) => exit_with_error(e),
+                                    };
+                                    let mut json = entry_json(&entry, Some(&d.deleted_in), None);
+                                    json["deleted"] = json!(json_time(d.deleted_at));
+                                    if list.is_present("body") {
+                                        json["body"] = serde_json::Value::Null;
+                                    }
+                                    values.push(json);
+                                }
+                            }
+                            print_json(values, format);
+                            return;
+                        }
+
                         for e in entries {
-                            println!("{}", e);
+                            let mut line = e.name;
+                            if let Some(title) = e.metadata.title {
+                                line.push_str(&format!("  {}", title));
+                            }
+                            for tag in e.metadata.tags {
+                                line.push_str(&format!(" #{}", tag));
+                            }
+                            println!("{}", line);
+                        }
+                        if list.is_present("deleted") {
+                            match deary.deleted_entries() {
+                                Ok(deleted) if !deleted.is_empty() => {
+                                    println!("\nDeleted:");
+                                    for d in deleted {
+                                        println!("{}", d.name);
+                                    }
+                                }
+                                Ok(_) => {}
+                                Err(e) => exit_with_error(e),
+                            }
                         }
                     }
                     Err(e) => exit_with_error(e),
@@ -117,6 +758,228 @@ fn main() {
                 Err(e) => exit_with_error(e),
             };
         }
+        ("trash", Some(_)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary.deleted_entries() {
+                    Ok(deleted) => {
+                        for d in deleted {
+                            println!(
+                                "{} (deleted {})",
+                                d.name,
+                                deary.local_time(d.deleted_at).format("%Y-%m-%d %H:%M:%S")
+                            );
+                        }
+                    }
+                    Err(e) => exit_with_error(e),
+                },
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("purge", Some(purge)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.purge_entry(purge.value_of("name").unwrap()) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("undelete", Some(undelete)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.undelete_entry(undelete.value_of("name").unwrap()) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("tag", Some(tag)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    let result = match tag.subcommand() {
+                        ("add", Some(add)) => deary.add_tags(
+                            &find_entry(&deary, add.value_of("name").unwrap()),
+                            &add.values_of("tag").unwrap().collect::<Vec<&str>>(),
+                        ),
+                        ("remove", Some(remove)) => deary.remove_tags(
+                            &find_entry(&deary, remove.value_of("name").unwrap()),
+                            &remove.values_of("tag").unwrap().collect::<Vec<&str>>(),
+                        ),
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = result {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("tags", Some(_)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary.tags() {
+                    Ok(tags) => {
+                        for (tag, count) in tags {
+                            println!("{}\t{}", tag, count);
+                        }
+                    }
+                    Err(e) => exit_with_error(e),
+                },
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("reindex", Some(_)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.rebuild_metadata() {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("reencrypt", Some(reencrypt)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    let recipients: Vec<&str> = reencrypt.values_of("key_id").unwrap().collect();
+                    if let Err(e) = deary.change_recipients(&recipients) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("git", Some(git)) => {
+            if let ("remote", Some(remote)) = git.subcommand() {
+                match Deary::new(&repo_path(diary)) {
+                    Ok(deary) => {
+                        let result = match remote.subcommand() {
+                            ("add", Some(add)) => deary.add_remote(
+                                add.value_of("name").unwrap(),
+                                add.value_of("url").unwrap(),
+                            ),
+                            ("remove", Some(remove)) => {
+                                deary.remove_remote(remove.value_of("name").unwrap())
+                            }
+                            _ => deary.remotes().map(|remotes| {
+                                for (name, url) in remotes {
+                                    println!("{}\t{}", name, url);
+                                }
+                            }),
+                        };
+                        if let Err(e) = result {
+                            exit_with_error(e);
+                        }
+                    }
+                    Err(e) => exit_with_error(e),
+                };
+            }
+        }
+        ("push", Some(push)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.push(push.value_of("remote"), push.is_present("force")) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("pull", Some(pull)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.pull(pull.value_of("remote")) {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("make-private", Some(_)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => {
+                    if let Err(e) = deary.make_private() {
+                        exit_with_error(e);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("verify-history", Some(_)) => {
+            match Deary::new(&repo_path(diary)) {
+                Ok(deary) => match deary.verify_history() {
+                    Ok(commits) => {
+                        let mut failed = 0;
+                        for commit in commits {
+                            if let Verification::Good { trusted: true, .. } = commit.verification {
+                                continue;
+                            }
+                            failed += 1;
+                            println!("{} {}: {}", commit.id, commit.summary, commit.verification);
+                        }
+                        if failed > 0 {
+                            exit_with_error(DearyError::Verification(format!(
+                                "{} commit(s) not signed by a trusted key",
+                                failed
+                            )));
+                        }
+                    }
+                    Err(e) => exit_with_error(e),
+                },
+                Err(e) => exit_with_error(e),
+            };
+        }
+        ("diaries", Some(_)) => match list_diaries() {
+            Ok(diaries) => {
+                let current = repo_path(diary);
+                for (name, path) in diaries {
+                    let marker = if path == current { '*' } else { ' ' };
+                    let recipients = match Deary::new(&path) {
+                        Ok(deary) => deary.recipients().unwrap_or_default().join(", "),
+                        Err(_) => String::new(),
+                    };
+                    println!("{} {}\t{}\t{}", marker, name, path.display(), recipients);
+                }
+            }
+            Err(e) => exit_with_error(e),
+        },
+        ("config", Some(config)) => match config.subcommand() {
+            ("get", Some(get)) => {
+                let settings = match Config::load(Some(&repo_path(diary))) {
+                    Ok(settings) => settings,
+                    Err(e) => exit_with_error(e),
+                };
+                let keys = match get.value_of("key") {
+                    Some(key) => vec![key],
+                    None => CONFIG_KEYS.to_vec(),
+                };
+                for key in &keys {
+                    match settings.get(key) {
+                        Ok(Some(value)) if keys.len() == 1 => println!("{}", value),
+                        Ok(Some(value)) => println!("{} = {}", key, value),
+                        Ok(None) => {}
+                        Err(e) => exit_with_error(e),
+                    }
+                }
+            }
+            ("set", Some(set)) => {
+                let key = set.value_of("key").unwrap();
+                let values: Vec<&str> = set.values_of("value").unwrap().collect();
+                let result = if set.is_present("repo") {
+                    match Deary::new(&repo_path(diary)) {
+                        Ok(deary) => deary.set_diary_config(key, &values),
+                        Err(e) => exit_with_error(e),
+                    }
+                } else {
+                    Config::set_user(key, &values)
+                };
+                if let Err(e) = result {
+                    exit_with_error(e);
+                }
+            }
+            _ => {}
+        },
         _ => {}
     }
 }
@@ -0,0 +1,521 @@
+//! Entry metadata from an optional front matter block, kept in an encrypted index so that
+//! listing entries does not need to decrypt every one of them.
+
+use crate::{
+    Change, Cipher, Deary, DearyError, Period, Result, ENTRY_NAME_FORMAT, METADATA_FILE_NAME,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::fs::File;
+use std::io::prelude::*;
+
+const FRONT_MATTER_DELIMITER: &str = "---";
+
+/// Metadata of an entry, from the front matter at its start:
+///
+/// ```text
+/// ---
+/// title: A day at the sea
+/// tags: travel, family
+/// mood: happy
+/// location: Odesa
+/// ---
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    pub mood: Option<String>,
+    pub location: Option<String>,
+}
+
+impl Metadata {
+    /// Parses the front matter at the start of an entry: `key: value` lines between two `---`
+    /// lines, as above. Tags can also be written as a list, `[travel, family]`. Unknown keys
+    /// are ignored, and an entry without front matter has no metadata.
+    pub fn parse(text: &[u8]) -> Metadata {
+        let text = String::from_utf8_lossy(text);
+        let lines: Vec<&str> = text.lines().collect();
+        let mut metadata = Metadata::default();
+        let end = match front_matter_end(&lines) {
+            Some(end) => end,
+            None => return metadata,
+        };
+
+        for (key, value) in lines[1..end].iter().filter_map(|l| l.split_once(':')) {
+            let key = key.trim().to_lowercase();
+            let value = unquote(value);
+            let value = if value.is_empty() { None } else { Some(value) };
+            match key.as_str() {
+                "title" => metadata.title = value,
+                "mood" => metadata.mood = value,
+                "location" => metadata.location = value,
+                "tags" => metadata.tags = parse_tags(value.as_deref().unwrap_or_default()),
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    /// Tells whether the entry has every tag in `filter`, except those prefixed with `-`, which
+    /// it must not have. Tags are compared ignoring case.
+    pub fn matches_tags(&self, filter: &[&str]) -> bool {
+        filter.iter().all(|tag| match tag.strip_prefix('-') {
+            Some(tag) => !self.has_tag(tag),
+            None => self.has_tag(tag),
+        })
+    }
+
+    fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim_start_matches('#');
+        self.tags.iter().any(|t| same_tag(t, tag))
+    }
+}
+
+/// An entry with its metadata, see [`Deary::list_entries_with_metadata`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryInfo {
+    pub name: String,
+    /// When the entry was created, unless the entry is not in the metadata index and its name
+    /// is not a timestamp.
+    pub created: Option<DateTime<Utc>>,
+    /// The size of the entry's text in bytes, unless the entry is not in the metadata index.
+    pub size: Option<u64>,
+    pub metadata: Metadata,
+}
+
+impl EntryInfo {
+    /// Tells whether the entry was created in or after `since`, and in or before `until`.
+    /// Entries of unknown creation time are only between two open ends.
+    pub fn created_between(&self, since: Option<Period>, until: Option<Period>) -> bool {
+        match self.created {
+            Some(created) => {
+                since.is_none_or(|p| created >= p.start) && until.is_none_or(|p| created < p.end)
+            }
+            None => since.is_none() && until.is_none(),
+        }
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Lists entries along with their metadata, oldest first. Metadata is read from the
+    /// metadata index without decrypting the entries; entries missing from the index (e.g.
+    /// written before it existed, see [`Deary::rebuild_metadata`]) have none.
+    pub fn list_entries_with_metadata(&self) -> Result<Vec<EntryInfo>> {
+        let index = self.read_metadata_index()?;
+        let mut entries: Vec<EntryInfo> = self
+            .list_entries()?
+            .into_iter()
+            .map(|name| match index.iter().find(|e| e.name == name) {
+                Some(entry) => entry.clone(),
+                None => EntryInfo {
+                    created: self.created_from_name(&name),
+                    name,
+                    size: None,
+                    metadata: Metadata::default(),
+                },
+            })
+            .collect();
+        // Names are creation times too, except for entries that deary did not name
+        entries.sort_by_key(|e| e.created);
+        Ok(entries)
+    }
+
+    /// Lists the tags of all entries, along with the number of entries having each, most used
+    /// first.
+    pub fn tags(&self) -> Result<Vec<(String, usize)>> {
+        let mut counts: Vec<(String, usize)> = vec![];
+        for entry in self.list_entries_with_metadata()? {
+            for tag in entry.metadata.tags {
+                match counts.iter_mut().find(|(t, _)| same_tag(t, &tag)) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((tag, 1)),
+                }
+            }
+        }
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        Ok(counts)
+    }
+
+    /// Adds `tags` to the front matter of an entry, as a new commit.
+    pub fn add_tags(&self, name: &str, tags: &[&str]) -> Result<()> {
+        let mut new_tags = vec![];
+        for tag in tags {
+            new_tags.push(validate_tag(tag)?);
+        }
+        self.change_tags(name, |current| {
+            for tag in new_tags {
+                if !current.iter().any(|t| same_tag(t, &tag)) {
+                    current.push(tag);
+                }
+            }
+        })
+    }
+
+    /// Removes `tags` from the front matter of an entry, as a new commit.
+    pub fn remove_tags(&self, name: &str, tags: &[&str]) -> Result<()> {
+        self.change_tags(name, |current| {
+            current.retain(|t| {
+                !tags
+                    .iter()
+                    .any(|tag| same_tag(t, tag.trim_start_matches('#')))
+            })
+        })
+    }
+
+    fn change_tags<F: FnOnce(&mut Vec<String>)>(&self, name: &str, change: F) -> Result<()> {
+        let file_name = self.entry_file(name)?;
+        let file_path = self.repo_dir().join(&file_name);
+        let text = self.decrypt_entry(&file_path)?;
+        let old_tags = Metadata::parse(&text).tags;
+        let mut tags = old_tags.clone();
+        change(&mut tags);
+        if tags == old_tags {
+            return Ok(());
+        }
+
+        let text = set_front_matter_tags(&text, &tags);
+        let ciphertext = self.encrypt_text(&text, &self.recipients()?)?;
+        File::create(file_path)?.write_all(&ciphertext)?;
+        self.update_metadata(name, &text)?;
+        self.commit_entry(&file_name, Change::Edit)
+    }
+
+    /// Parses the front matter of every entry into a new metadata index, and commits it.
+    pub fn rebuild_metadata(&self) -> Result<()> {
+        let old_index = self.read_metadata_index()?;
+        let mut index = vec![];
+        for (name, file_name) in self.entry_files()? {
+            let text = self.decrypt_entry(&self.repo_dir().join(&file_name))?;
+            let created = match old_index.iter().find(|e| e.name == name) {
+                Some(entry) => entry.created,
+                None => match self.created_from_name(&name) {
+                    Some(created) => Some(created),
+                    None => self.entry_history(&name)?.last().map(|r| r.time),
+                },
+            };
+            index.push(EntryInfo {
+                name,
+                created,
+                size: Some(text.len() as u64),
+                metadata: Metadata::parse(&text),
+            });
+        }
+        index.sort_by(|a, b| a.name.cmp(&b.name));
+        self.write_metadata_index(&index)?;
+        self.commit_changes(
+            &[(METADATA_FILE_NAME, Change::Edit)],
+            "Rebuild metadata index",
+            false,
+        )
+    }
+
+    /// Records the metadata of entry `name` from its new `text`, keeping its creation time if
+    /// the entry is already in the index.
+    pub(crate) fn update_metadata(&self, name: &str, text: &[u8]) -> Result<()> {
+        let mut index = self.read_metadata_index()?;
+        let metadata = Metadata::parse(text);
+        let size = Some(text.len() as u64);
+        match index.iter_mut().find(|e| e.name == name) {
+            Some(entry) => {
+                entry.size = size;
+                entry.metadata = metadata;
+            }
+            None => index.push(EntryInfo {
+                name: name.to_string(),
+                created: self.created_from_name(name).or_else(|| Some(Utc::now())),
+                size,
+                metadata,
+            }),
+        }
+        self.write_metadata_index(&index)
+    }
+
+    /// Removes entry `name` from the metadata index, if there is one.
+    pub(crate) fn remove_metadata(&self, name: &str) -> Result<()> {
+        if !self.has_metadata_index() {
+            return Ok(());
+        }
+        let mut index = self.read_metadata_index()?;
+        index.retain(|e| e.name != name);
+        self.write_metadata_index(&index)
+    }
+
+    /// The creation time of an entry named after it, like every entry `deary create` makes,
+    /// with the configured name format or the default one.
+    pub(crate) fn created_from_name(&self, name: &str) -> Option<DateTime<Utc>> {
+        let mut formats = vec![ENTRY_NAME_FORMAT];
+        if let Some(format) = &self.config.entry_name_format {
+            formats.insert(0, format);
+        }
+        formats
+            .into_iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(name, format).ok())
+            .map(|dt| DateTime::from_naive_utc_and_offset(dt, Utc))
+    }
+
+    pub(crate) fn has_metadata_index(&self) -> bool {
+        self.repo_dir().join(METADATA_FILE_NAME).exists()
+    }
+
+    /// Reads the encrypted metadata index, see [`parse_metadata_index`]. A diary without one
+    /// has an empty index.
+    fn read_metadata_index(&self) -> Result<Vec<EntryInfo>> {
+        if !self.has_metadata_index() {
+            return Ok(vec![]);
+        }
+        let text = self.decrypt_entry(&self.repo_dir().join(METADATA_FILE_NAME))?;
+        Ok(parse_metadata_index(&text))
+    }
+
+    fn write_metadata_index(&self, index: &[EntryInfo]) -> Result<()> {
+        let ciphertext = self.encrypt_text(&format_metadata_index(index), &self.recipients()?)?;
+        File::create(self.repo_dir().join(METADATA_FILE_NAME))?.write_all(&ciphertext)?;
+        Ok(())
+    }
+}
+
+/// Replaces the tags in the front matter of `text`, leaving everything else as it is. A front
+/// matter block is added if there is none.
+pub(crate) fn set_front_matter_tags(text: &[u8], tags: &[String]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(text);
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let tags_line = if tags.is_empty() {
+        None
+    } else {
+        Some(format!("tags: {}\n", tags.join(", ")))
+    };
+
+    let end = match front_matter_end(&lines) {
+        Some(end) => end,
+        None => match tags_line {
+            Some(tags_line) => {
+                let delimiter = format!("{}\n", FRONT_MATTER_DELIMITER);
+                return [delimiter.as_str(), &tags_line, &delimiter, &text]
+                    .concat()
+                    .into_bytes();
+            }
+            None => return text.into_owned().into_bytes(),
+        },
+    };
+
+    let is_tags = |line: &str| match line.split_once(':') {
+        Some((key, _)) => key.trim().eq_ignore_ascii_case("tags"),
+        None => false,
+    };
+    let mut output = lines[0].to_string();
+    let mut tags_line = tags_line;
+    for line in &lines[1..end] {
+        if is_tags(line) {
+            output.push_str(&tags_line.take().unwrap_or_default());
+        } else {
+            output.push_str(line);
+        }
+    }
+    output.push_str(&tags_line.unwrap_or_default());
+    output.push_str(&lines[end..].concat());
+    output.into_bytes()
+}
+
+/// Returns the index of the line closing the front matter, if `lines` start with one.
+fn front_matter_end(lines: &[&str]) -> Option<usize> {
+    let is_delimiter = |line: &&str| line.trim_end() == FRONT_MATTER_DELIMITER;
+    if !lines.first().is_some_and(is_delimiter) {
+        return None;
+    }
+    lines.iter().skip(1).position(is_delimiter).map(|i| i + 1)
+}
+
+/// Parses a list of tags, separated by commas and optionally enclosed in brackets. A leading
+/// `#` is dropped, as are duplicates.
+fn parse_tags(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(value);
+
+    let mut tags: Vec<String> = vec![];
+    for tag in value.split(',') {
+        let tag = unquote(tag);
+        let tag = tag.trim_start_matches('#').trim();
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// Checks that `tag` can be stored in front matter and used in a filter, and drops a leading `#`.
+fn validate_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim().trim_start_matches('#');
+    if tag.is_empty() || tag.starts_with('-') || tag.contains(|c: char| c == ',' || c.is_control())
+    {
+        return Err(DearyError::InvalidInput(format!("Invalid tag: {}", tag)));
+    }
+    Ok(tag.to_string())
+}
+
+fn same_tag(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in &['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(*quote)
+            .and_then(|v| v.strip_suffix(*quote))
+        {
+            return unquoted.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// Parses the metadata index: one entry per line, with its name, creation time (RFC 3339),
+/// title, comma-separated tags, mood, location and size, separated by tabs.
+pub(crate) fn parse_metadata_index(text: &[u8]) -> Vec<EntryInfo> {
+    let optional = |field: Option<&str>| match field {
+        Some(f) if !f.is_empty() => Some(f.to_string()),
+        _ => None,
+    };
+
+    let mut index = vec![];
+    for line in String::from_utf8_lossy(text).lines() {
+        let mut fields = line.split('\t');
+        let name = match fields.next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => continue,
+        };
+        let created = fields
+            .next()
+            .and_then(|c| DateTime::parse_from_rfc3339(c).ok())
+            .map(|c| c.with_timezone(&Utc));
+        let title = optional(fields.next());
+        let tags = parse_tags(fields.next().unwrap_or_default());
+        let mood = optional(fields.next());
+        let location = optional(fields.next());
+        let size = fields.next().and_then(|s| s.parse().ok());
+        index.push(EntryInfo {
+            name,
+            created,
+            size,
+            metadata: Metadata {
+                title,
+                tags,
+                mood,
+                location,
+            },
+        });
+    }
+    index
+}
+
+pub(crate) fn format_metadata_index(index: &[EntryInfo]) -> Vec<u8> {
+    // Front matter values are single lines, but may contain tabs
+    let field = |value: &Option<String>| value.as_deref().unwrap_or_default().replace('\t', " ");
+
+    let mut text = String::new();
+    for entry in index {
+        let metadata = &entry.metadata;
+        text.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            entry.name,
+            entry.created.map(|c| c.to_rfc3339()).unwrap_or_default(),
+            field(&metadata.title),
+            metadata.tags.join(",").replace('\t', " "),
+            field(&metadata.mood),
+            field(&metadata.location),
+            entry.size.map(|s| s.to_string()).unwrap_or_default(),
+        ));
+    }
+    text.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{front_matter_end, set_front_matter_tags};
+
+    fn set_tags(text: &str, tags: &[&str]) -> String {
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        String::from_utf8(set_front_matter_tags(text.as_bytes(), &tags)).unwrap()
+    }
+
+    #[test]
+    fn front_matter_must_start_and_end_with_a_delimiter() {
+        assert_eq!(front_matter_end(&[]), None);
+        assert_eq!(front_matter_end(&["Dear diary\n", "---\n"]), None);
+        assert_eq!(front_matter_end(&["---\n", "tags: a\n"]), None);
+        assert_eq!(front_matter_end(&["---\n", "---\n", "---\n"]), Some(1));
+        assert_eq!(
+            front_matter_end(&["---\r\n", "tags: a\r\n", "---  \n", "Dear diary"]),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn tags_are_added_in_new_front_matter() {
+        assert_eq!(
+            set_tags("Dear diary\n", &["work", "travel"]),
+            "---\ntags: work, travel\n---\nDear diary\n"
+        );
+        assert_eq!(set_tags("", &["work"]), "---\ntags: work\n---\n");
+        // An unclosed block is not front matter
+        assert_eq!(
+            set_tags("---\nDear diary", &["work"]),
+            "---\ntags: work\n---\n---\nDear diary"
+        );
+    }
+
+    #[test]
+    fn no_front_matter_is_added_without_tags() {
+        assert_eq!(set_tags("Dear diary\n", &[]), "Dear diary\n");
+        assert_eq!(set_tags("", &[]), "");
+    }
+
+    #[test]
+    fn tags_line_is_replaced_in_place() {
+        assert_eq!(
+            set_tags(
+                "---\ntitle: Trip\nTags: [a, b]\nmood: good\n---\nDear diary\n",
+                &["c"]
+            ),
+            "---\ntitle: Trip\ntags: c\nmood: good\n---\nDear diary\n"
+        );
+    }
+
+    #[test]
+    fn tags_line_is_added_to_front_matter_without_one() {
+        assert_eq!(
+            set_tags("---\ntitle: Trip\n---\nDear diary\n", &["a", "b"]),
+            "---\ntitle: Trip\ntags: a, b\n---\nDear diary\n"
+        );
+        assert_eq!(
+            set_tags("---\n---\nDear diary\n", &["a"]),
+            "---\ntags: a\n---\nDear diary\n"
+        );
+    }
+
+    #[test]
+    fn empty_tags_remove_the_tags_line() {
+        assert_eq!(
+            set_tags(
+                "---\ntitle: Trip\ntags: a\n---\ntags: not front matter\n",
+                &[]
+            ),
+            "---\ntitle: Trip\n---\ntags: not front matter\n"
+        );
+        assert_eq!(
+            set_tags("---\ntitle: Trip\n---\nDear diary\n", &[]),
+            "---\ntitle: Trip\n---\nDear diary\n"
+        );
+    }
+
+    #[test]
+    fn text_after_front_matter_is_kept_as_it_is() {
+        assert_eq!(
+            set_tags("---\ntags: a\n---\r\nno newline at the end", &["b"]),
+            "---\ntags: b\n---\r\nno newline at the end"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Removing an entry from the whole history, for content that must really be gone.
+
+use crate::history::blob_id;
+use crate::metadata::{format_metadata_index, parse_metadata_index};
+use crate::{
+    format_index, parse_index, parse_recipients, Change, Cipher, Deary, DearyError, Result,
+    AGE_RECIPIENTS_FILE_NAME, GPG_ID_FILE_NAME, INDEX_FILE_NAME, METADATA_FILE_NAME,
+    PRIVATE_COMMIT_MESSAGE,
+};
+use git2::build::CheckoutBuilder;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+impl<C: Cipher> Deary<C> {
+    /// Deletes an entry (if it is not deleted yet) and rewrites every commit to drop it, and its
+    /// metadata, leaving out commits that become empty. Reflogs are then deleted and unreachable
+    /// objects pruned, so no trace of the entry's ciphertext is left in the repository.
+    ///
+    /// Every commit from the first one that had the entry on gets a new ID, so remotes have to
+    /// be updated with a forced push.
+    pub fn purge_entry(&self, name: &str) -> Result<()> {
+        if self.list_entries()?.iter().any(|n| n == name) {
+            self.delete_entry(name)?;
+        }
+        let file_name = match self.deleted_entries()?.into_iter().find(|d| d.name == name) {
+            Some(entry) => entry.file_name,
+            None => return Err(DearyError::EntryNotFound(name.to_string())),
+        };
+        // Entries renamed by `make_private` are under their own name in older commits
+        let mut paths = vec![file_name];
+        if paths[0] != name {
+            paths.push(name.to_string());
+        }
+
+        let rewritten = self.rewrite_history(name, &paths)?;
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            let (name, target) = match (reference.name(), reference.target()) {
+                (Some(name), Some(target)) => (name.to_string(), target),
+                _ => continue,
+            };
+            if let Some(new_target) = rewritten.get(&target) {
+                self.repo
+                    .reference(&name, *new_target, true, "purge: rewrite history")?;
+            }
+        }
+        self.repo
+            .checkout_head(Some(CheckoutBuilder::new().force()))?;
+
+        self.expire_reflogs()?;
+        self.prune()
+    }
+
+    /// Rewrites every commit, oldest first, without `paths`, without entry `name` in the metadata
+    /// index and the index of a private diary, and without commit messages naming the entry.
+    /// Returns the new ID of every rewritten commit; dropped commits map to the new ID of their
+    /// parent.
+    fn rewrite_history(
+        &self,
+        name: &str,
+        paths: &[String],
+    ) -> Result<HashMap<git2::Oid, git2::Oid>> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+        revwalk.push_glob("*")?;
+
+        let mut rewritten = HashMap::new();
+        let mut texts = HashMap::new();
+        for oid in revwalk {
+            let commit = self.repo.find_commit(oid?)?;
+
+            let mut parent_ids: Vec<git2::Oid> = vec![];
+            for parent in commit.parent_ids() {
+                let parent = *rewritten.get(&parent).unwrap_or(&parent);
+                if !parent_ids.contains(&parent) {
+                    parent_ids.push(parent);
+                }
+            }
+            let mut parents = vec![];
+            for id in &parent_ids {
+                parents.push(self.repo.find_commit(*id)?);
+            }
+
+            let mut builder = self.repo.treebuilder(Some(&commit.tree()?))?;
+            for path in paths {
+                if builder.get(path)?.is_some() {
+                    builder.remove(path)?;
+                }
+            }
+            let purge_metadata = |text: &[u8]| {
+                let mut index = parse_metadata_index(text);
+                let had_entry = index.iter().any(|e| e.name == name);
+                index.retain(|e| e.name != name);
+                (had_entry, format_metadata_index(&index))
+            };
+            let purge_index = |text: &[u8]| {
+                let mut index = parse_index(text);
+                let had_entry = index.iter().any(|(n, _)| n == name);
+                index.retain(|(n, _)| n != name);
+                (had_entry, format_index(&index))
+            };
+            let parent = parents.first();
+            self.purge_index_file(
+                METADATA_FILE_NAME,
+                purge_metadata,
+                &commit,
+                parent,
+                &mut builder,
+                &mut texts,
+            )?;
+            self.purge_index_file(
+                INDEX_FILE_NAME,
+                purge_index,
+                &commit,
+                parent,
+                &mut builder,
+                &mut texts,
+            )?;
+            let tree = self.repo.find_tree(builder.write()?)?;
+            let message = commit.message().unwrap_or_default();
+            let message = if names_entry(message, paths) {
+                PRIVATE_COMMIT_MESSAGE
+            } else {
+                message
+            };
+
+            // Commits from before the entry existed stay as they are
+            if tree.id() == commit.tree_id()
+                && commit.parent_ids().eq(parent_ids.iter().copied())
+                && Some(message) == commit.message()
+            {
+                continue;
+            }
+            // A commit that only touched the purged entry has nothing left to record
+            if parents.len() == 1 && parents[0].tree_id() == tree.id() {
+                rewritten.insert(commit.id(), parents[0].id());
+                continue;
+            }
+
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            let new_id = self.write_commit(
+                &commit.author(),
+                &commit.committer(),
+                message,
+                &tree,
+                &parents,
+            )?;
+            rewritten.insert(commit.id(), new_id);
+        }
+        Ok(rewritten)
+    }
+
+    /// Rewrites the encrypted index `file_name` (the metadata index, or the index of a private
+    /// diary) of `commit`, in `builder`, with `purge`, which removes the entry from an index and
+    /// tells whether it was in it.
+    ///
+    /// As encryption is not deterministic, an index that ends up like the one of `parent`, the
+    /// rewritten first parent, has to reuse its blob, so that commits that only changed the
+    /// entry are left out. That only holds if both commits have the same recipients: an index
+    /// re-encrypted to new recipients must stay that way. Otherwise, an index without the entry
+    /// is left as it is, and one with the entry is encrypted again, to the recipients of
+    /// `commit`. A metadata index that is left empty, where `parent` had none, is removed.
+    /// `texts` caches decrypted blobs.
+    fn purge_index_file(
+        &self,
+        file_name: &str,
+        purge: impl Fn(&[u8]) -> (bool, Vec<u8>),
+        commit: &git2::Commit,
+        parent: Option<&git2::Commit>,
+        builder: &mut git2::TreeBuilder,
+        texts: &mut HashMap<git2::Oid, Vec<u8>>,
+    ) -> Result<()> {
+        let id = match builder.get(file_name)?.map(|e| e.id()) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let (had_entry, text) = purge(&self.decrypt_blob_cached(id, texts)?);
+
+        let parent_id = parent.and_then(|p| blob_id(p, file_name));
+        if let (Some(parent), Some(parent_id)) = (parent, parent_id) {
+            if recipients_id(parent) == recipients_id(commit)
+                && purge(&self.decrypt_blob_cached(parent_id, texts)?).1 == text
+            {
+                builder.insert(file_name, parent_id, 0o100644)?;
+                return Ok(());
+            }
+        }
+        if !had_entry {
+            return Ok(());
+        }
+        if parent_id.is_none() && text.is_empty() && file_name == METADATA_FILE_NAME {
+            builder.remove(file_name)?;
+            return Ok(());
+        }
+        let recipients = match recipients_id(commit) {
+            Some(id) => {
+                parse_recipients(&String::from_utf8_lossy(self.repo.find_blob(id)?.content()))
+            }
+            None => self.recipients()?,
+        };
+        let new_id = self.repo.blob(&self.encrypt_text(&text, &recipients)?)?;
+        texts.insert(new_id, text);
+        builder.insert(file_name, new_id, 0o100644)?;
+        Ok(())
+    }
+
+    fn decrypt_blob_cached(
+        &self,
+        id: git2::Oid,
+        texts: &mut HashMap<git2::Oid, Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        if let Some(text) = texts.get(&id) {
+            return Ok(text.clone());
+        }
+        let text = self.cipher.decrypt(self.repo.find_blob(id)?.content())?;
+        texts.insert(id, text.clone());
+        Ok(text)
+    }
+
+    /// Deletes the reflogs of HEAD and every reference, which still point to the old commits.
+    fn expire_reflogs(&self) -> Result<()> {
+        self.repo.reflog_delete("HEAD")?;
+        for reference in self.repo.references()? {
+            if let Some(name) = reference?.name() {
+                self.repo.reflog_delete(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Packs every object reachable from a reference into a single new pack, and deletes all
+    /// other objects, like `git repack -a -d` followed by `git prune`.
+    fn prune(&self) -> Result<()> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_glob("*")?;
+        revwalk.push_head()?;
+
+        let mut builder = self.repo.packbuilder()?;
+        builder.insert_walk(&mut revwalk)?;
+        let mut pack = git2::Buf::new();
+        builder.write_buf(&mut pack)?;
+        // Packs are named after their checksum, the last 20 bytes of the pack
+        let checksum = &pack[pack.len().saturating_sub(20)..];
+        let pack_name = format!("pack-{}", git2::Oid::from_bytes(checksum)?);
+
+        let odb = self.repo.odb()?;
+        let mut writer = odb.packwriter()?;
+        std::io::Write::write_all(&mut writer, &pack)?;
+        writer.commit()?;
+
+        let objects_dir = self.repo.path().join("objects");
+        for dir in fs::read_dir(&objects_dir)? {
+            let dir = dir?;
+            let dir_name = dir.file_name().to_string_lossy().into_owned();
+            // Loose objects are in directories named after the first two digits of their ID
+            if dir_name.len() == 2 && dir_name.chars().all(|c| c.is_ascii_hexdigit()) {
+                fs::remove_dir_all(dir.path())?;
+            }
+        }
+        for file in fs::read_dir(objects_dir.join("pack"))? {
+            let path = file?.path();
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let is_pack = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("pack") | Some("idx")
+            );
+            if is_pack && stem.starts_with("pack-") && stem != pack_name {
+                fs::remove_file(&path)?;
+            }
+        }
+        remove_if_exists(&self.repo.path().join("FETCH_HEAD"))?;
+        remove_if_exists(&self.repo.path().join("ORIG_HEAD"))
+    }
+}
+
+/// Tells whether `message` is that of a commit changing one of `paths`, which names it, see
+/// [`Deary::commit_entry`].
+fn names_entry(message: &str, paths: &[String]) -> bool {
+    paths.iter().any(|path| {
+        [Change::Add, Change::Edit, Change::Delete]
+            .iter()
+            .any(|change| message.trim_end() == format!("{:?} {}", change, path))
+    })
+}
+
+/// The ID of the recipients file in `commit`.
+fn recipients_id(commit: &git2::Commit) -> Option<git2::Oid> {
+    blob_id(commit, GPG_ID_FILE_NAME).or_else(|| blob_id(commit, AGE_RECIPIENTS_FILE_NAME))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DearyError::from(e)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::blob_id;
+    use crate::testing::MockCipher;
+    use crate::testing::{mock_diary, mock_entry, mock_recipients};
+    use crate::{Cipher, Deary, INDEX_FILE_NAME, METADATA_FILE_NAME};
+
+    /// Every commit reachable from a reference, newest first.
+    fn all_commits(deary: &Deary<MockCipher>) -> Vec<git2::Commit<'_>> {
+        let mut revwalk = deary.repo.revwalk().unwrap();
+        revwalk.push_glob("*").unwrap();
+        revwalk
+            .map(|oid| deary.repo.find_commit(oid.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn purging_after_changing_recipients_keeps_the_new_recipients() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deary = mock_diary(dir.path(), &["alice"]);
+        let kept = mock_entry(&mut deary, "kept");
+        deary.change_recipients(&["alice", "bob"]).unwrap();
+        let purged = mock_entry(&mut deary, "secret");
+
+        deary.purge_entry(&purged).unwrap();
+
+        assert_eq!(deary.list_entries().unwrap(), vec![kept.clone()]);
+        let head = deary.repo.head().unwrap().peel_to_commit().unwrap();
+        let metadata = deary
+            .repo
+            .find_blob(blob_id(&head, METADATA_FILE_NAME).unwrap())
+            .unwrap();
+        assert_eq!(mock_recipients(metadata.content()), vec!["alice", "bob"]);
+        assert_eq!(deary.list_entries_with_metadata().unwrap()[0].name, kept);
+
+        // The commit that changed the recipients still re-encrypts the metadata index
+        assert_eq!(head.summary(), Some("Change recipients"));
+        let parent = head.parent(0).unwrap();
+        assert_ne!(
+            blob_id(&parent, METADATA_FILE_NAME),
+            blob_id(&head, METADATA_FILE_NAME)
+        );
+    }
+
+    #[test]
+    fn purging_leaves_no_commit_naming_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deary = mock_diary(dir.path(), &["alice"]);
+        // The first entry adds the metadata index, which would be left empty
+        let purged = mock_entry(&mut deary, "secret");
+        let kept = mock_entry(&mut deary, "kept");
+
+        deary.purge_entry(&purged).unwrap();
+
+        let commits = all_commits(&deary);
+        let messages: Vec<&str> = commits.iter().map(|c| c.summary().unwrap()).collect();
+        assert_eq!(
+            messages,
+            vec![format!("Add {}", kept).as_str(), "Add .gpg_id"]
+        );
+    }
+
+    #[test]
+    fn purging_removes_the_entry_from_every_private_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deary = mock_diary(dir.path(), &["alice"]);
+        deary.make_private().unwrap();
+        let purged = mock_entry(&mut deary, "secret");
+        let kept = mock_entry(&mut deary, "kept");
+        deary.update_entry(&kept).unwrap();
+
+        deary.purge_entry(&purged).unwrap();
+
+        assert_eq!(deary.list_entries().unwrap(), vec![kept]);
+        for commit in all_commits(&deary) {
+            for file_name in &[INDEX_FILE_NAME, METADATA_FILE_NAME] {
+                if let Some(id) = blob_id(&commit, file_name) {
+                    let blob = deary.repo.find_blob(id).unwrap();
+                    let text = deary.cipher.decrypt(blob.content()).unwrap();
+                    assert!(!String::from_utf8_lossy(&text).contains(&purged));
+                }
+            }
+        }
+    }
+}
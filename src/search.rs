@@ -0,0 +1,187 @@
+//! Searching the text of entries, which only exists decrypted, in memory.
+
+use crate::{Cipher, Deary, DearyError, Result};
+use regex::{Regex, RegexBuilder};
+
+/// How [`Deary::search`] matches its pattern.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchOptions {
+    /// Treat the pattern as a regular expression instead of a plain substring.
+    pub regex: bool,
+    pub ignore_case: bool,
+    /// The number of lines to include before and after every matching line.
+    pub context: usize,
+}
+
+/// A line of an entry found by [`Deary::search`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchLine {
+    /// The line's number, starting at 1.
+    pub number: usize,
+    pub text: String,
+    /// False for lines only included as context.
+    pub matched: bool,
+}
+
+/// Matching lines of an entry, along with their context. Matches whose context overlaps are
+/// in the same hit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub name: String,
+    pub lines: Vec<SearchLine>,
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Finds the lines of entries matching `pattern`. Only entries that may match according to
+    /// the search index are decrypted, except when searching for a regular expression, which
+    /// can match anything.
+    pub fn search(&self, pattern: &str, options: SearchOptions) -> Result<Vec<SearchHit>> {
+        let regex = build_regex(pattern, options)?;
+        let (index, mut texts) = self.refreshed_search_index()?;
+        let candidates = index.candidates(pattern);
+
+        let mut hits = vec![];
+        for (name, file_name) in self.entry_files()? {
+            if !options.regex && !candidates.contains(&name) {
+                continue;
+            }
+            let text = match texts.remove(&name) {
+                Some(text) => text,
+                None => self.decrypt_entry(&self.repo_dir().join(&file_name))?,
+            };
+            hits.extend(find_lines(
+                &name,
+                &String::from_utf8_lossy(&text),
+                &regex,
+                options,
+            ));
+        }
+        Ok(hits)
+    }
+}
+
+fn build_regex(pattern: &str, options: SearchOptions) -> Result<Regex> {
+    let pattern = if options.regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map_err(|e| DearyError::InvalidInput(format!("Invalid pattern: {}", e)))
+}
+
+fn find_lines(name: &str, text: &str, regex: &Regex, options: SearchOptions) -> Vec<SearchHit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut hits: Vec<SearchHit> = vec![];
+    // One past the last line in the current hit
+    let mut end = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if !regex.is_match(line) {
+            continue;
+        }
+        let start = i.saturating_sub(options.context);
+        if hits.is_empty() || start > end {
+            hits.push(SearchHit {
+                name: name.to_string(),
+                lines: vec![],
+            });
+            end = start;
+        }
+        let hit = hits.last_mut().unwrap();
+        for (j, text) in lines
+            .iter()
+            .enumerate()
+            .take(i + options.context + 1)
+            .skip(end)
+        {
+            hit.lines.push(SearchLine {
+                number: j + 1,
+                text: text.to_string(),
+                matched: j == i,
+            });
+        }
+        // A line added as context of an earlier match can match too
+        if let Some(line) = hit.lines.iter_mut().find(|l| l.number == i + 1) {
+            line.matched = true;
+        }
+        end = end.max((i + options.context + 1).min(lines.len()));
+    }
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_regex, find_lines, SearchHit, SearchOptions};
+
+    const TEXT: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight";
+
+    /// The numbers of the lines in each hit, negative for context lines.
+    fn hits(pattern: &str, text: &str, context: usize) -> Vec<Vec<isize>> {
+        let options = SearchOptions {
+            regex: true,
+            context,
+            ..SearchOptions::default()
+        };
+        let regex = build_regex(pattern, options).unwrap();
+        find_lines("entry", text, &regex, options)
+            .into_iter()
+            .map(|hit: SearchHit| {
+                assert_eq!(hit.name, "entry");
+                hit.lines
+                    .iter()
+                    .map(|l| {
+                        assert_eq!(l.text, text.lines().nth(l.number - 1).unwrap());
+                        if l.matched {
+                            l.number as isize
+                        } else {
+                            -(l.number as isize)
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_hits_without_matches() {
+        assert!(hits("nine", TEXT, 2).is_empty());
+        assert!(hits("one", "", 2).is_empty());
+    }
+
+    #[test]
+    fn matches_without_context_are_separate_hits() {
+        assert_eq!(hits("two|five", TEXT, 0), vec![vec![2], vec![5]]);
+        // but adjacent lines are one
+        assert_eq!(hits("^t", TEXT, 0), vec![vec![2, 3]]);
+    }
+
+    #[test]
+    fn context_stops_at_the_edges_of_the_text() {
+        assert_eq!(
+            hits("one|eight", TEXT, 2),
+            vec![vec![1, -2, -3], vec![-6, -7, 8]]
+        );
+    }
+
+    #[test]
+    fn overlapping_context_is_one_hit() {
+        assert_eq!(
+            hits("two|six", TEXT, 2),
+            vec![vec![-1, 2, -3, -4, -5, 6, -7, -8]]
+        );
+        assert_eq!(
+            hits("two|six", TEXT, 1),
+            vec![vec![-1, 2, -3], vec![-5, 6, -7]]
+        );
+        // Adjacent context is joined too
+        assert_eq!(hits("two|five", TEXT, 1), vec![vec![-1, 2, -3, -4, 5, -6]]);
+    }
+
+    #[test]
+    fn matches_within_context_are_marked() {
+        assert_eq!(hits("^t", TEXT, 2), vec![vec![-1, 2, 3, -4, -5]]);
+        assert_eq!(hits("e$", TEXT, 1), vec![vec![1, -2, 3, -4, 5, -6]]);
+    }
+}
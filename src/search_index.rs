@@ -0,0 +1,198 @@
+//! A persistent inverted index of the words in entries, so that searching only needs to decrypt
+//! the entries that may match. It is a local cache, encrypted like entries and kept in the git
+//! directory rather than committed. Every entry in it is tied to the blob it was indexed from,
+//! so entries changed in other ways than `create`, `edit` and `delete` (e.g. by a pull) are
+//! indexed again the next time it is used.
+
+use crate::{Cipher, Deary, DearyError, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+const SEARCH_INDEX_PATH: &str = "deary/search_index";
+
+#[derive(Default)]
+pub(crate) struct SearchIndex {
+    /// Every indexed entry, with the ID of the blob it was indexed from.
+    entries: BTreeMap<String, git2::Oid>,
+    /// Every word, with the entries it appears in.
+    words: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SearchIndex {
+    fn insert(&mut self, name: &str, blob_id: git2::Oid, text: &[u8]) {
+        self.remove(name);
+        self.entries.insert(name.to_string(), blob_id);
+        for word in words(&String::from_utf8_lossy(text)) {
+            self.words.entry(word).or_default().insert(name.to_string());
+        }
+    }
+
+    fn remove(&mut self, name: &str) {
+        if self.entries.remove(name).is_some() {
+            self.words.retain(|_, names| {
+                names.remove(name);
+                !names.is_empty()
+            });
+        }
+    }
+
+    /// The entries that may contain `pattern`: those with a word containing each word of the
+    /// pattern, ignoring case.
+    pub(crate) fn candidates(&self, pattern: &str) -> BTreeSet<String> {
+        let mut candidates: BTreeSet<String> = self.entries.keys().cloned().collect();
+        for pattern_word in words(pattern) {
+            let names: BTreeSet<String> = self
+                .words
+                .iter()
+                .filter(|(word, _)| word.contains(&pattern_word))
+                .flat_map(|(_, names)| names.iter().cloned())
+                .collect();
+            candidates.retain(|name| names.contains(name));
+        }
+        candidates
+    }
+
+    /// Parses the index: a line with the name and blob ID of every entry, separated by a tab,
+    /// then an empty line, then a line with every word and the names of the entries containing
+    /// it, all separated by tabs.
+    fn parse(text: &[u8]) -> SearchIndex {
+        let text = String::from_utf8_lossy(text);
+        let mut lines = text.lines();
+        let mut index = SearchIndex::default();
+        for line in lines.by_ref().take_while(|l| !l.is_empty()) {
+            if let Some((name, id)) = line.split_once('\t') {
+                if let Ok(id) = git2::Oid::from_str(id) {
+                    index.entries.insert(name.to_string(), id);
+                }
+            }
+        }
+        for line in lines {
+            let mut fields = line.split('\t');
+            if let Some(word) = fields.next() {
+                index
+                    .words
+                    .insert(word.to_string(), fields.map(String::from).collect());
+            }
+        }
+        index
+    }
+
+    fn format(&self) -> Vec<u8> {
+        let mut text = String::new();
+        for (name, id) in &self.entries {
+            text.push_str(&format!("{}\t{}\n", name, id));
+        }
+        text.push('\n');
+        for (word, names) in &self.words {
+            text.push_str(word);
+            for name in names {
+                text.push('\t');
+                text.push_str(name);
+            }
+            text.push('\n');
+        }
+        text.into_bytes()
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Adds an entry to the search index, or updates it, once its file has been written.
+    pub(crate) fn index_entry(&self, name: &str, text: &[u8]) -> Result<()> {
+        let mut index = self.read_search_index()?;
+        let file_path = self.repo_dir().join(self.entry_file(name)?);
+        index.insert(
+            name,
+            git2::Oid::hash_file(git2::ObjectType::Blob, file_path)?,
+            text,
+        );
+        self.write_search_index(&index)
+    }
+
+    pub(crate) fn unindex_entry(&self, name: &str) -> Result<()> {
+        if !self.search_index_path().exists() {
+            return Ok(());
+        }
+        let mut index = self.read_search_index()?;
+        index.remove(name);
+        self.write_search_index(&index)
+    }
+
+    /// Reads the search index, indexing every entry that changed since it was last indexed.
+    /// Returns the index along with the texts of those entries, which had to be decrypted.
+    pub(crate) fn refreshed_search_index(&self) -> Result<(SearchIndex, HashMap<String, Vec<u8>>)> {
+        let mut index = self.read_search_index()?;
+        let mut texts = HashMap::new();
+        let entry_files = self.entry_files()?;
+
+        let mut changed = false;
+        for (name, file_name) in &entry_files {
+            let file_path = self.repo_dir().join(file_name);
+            let blob_id = git2::Oid::hash_file(git2::ObjectType::Blob, &file_path)?;
+            if index.entries.get(name) != Some(&blob_id) {
+                let text = self.decrypt_entry(&file_path)?;
+                index.insert(name, blob_id, &text);
+                texts.insert(name.clone(), text);
+                changed = true;
+            }
+        }
+        let gone: Vec<String> = index
+            .entries
+            .keys()
+            .filter(|name| !entry_files.iter().any(|(n, _)| n == *name))
+            .cloned()
+            .collect();
+        for name in gone {
+            index.remove(&name);
+            changed = true;
+        }
+
+        if changed {
+            self.write_search_index(&index)?;
+        }
+        Ok((index, texts))
+    }
+
+    /// Deletes the search index, e.g. when it is encrypted to keys that should no longer read
+    /// the diary. It is built again by the next search.
+    pub(crate) fn remove_search_index(&self) -> Result<()> {
+        match fs::remove_file(self.search_index_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DearyError::from(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the search index. A missing index, or one that cannot be decrypted (e.g. because
+    /// the recipients changed), is empty, to be built again.
+    fn read_search_index(&self) -> Result<SearchIndex> {
+        match self.decrypt_entry(&self.search_index_path()) {
+            Ok(text) => Ok(SearchIndex::parse(&text)),
+            Err(DearyError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(SearchIndex::default())
+            }
+            Err(DearyError::Decryption(_)) => Ok(SearchIndex::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_search_index(&self, index: &SearchIndex) -> Result<()> {
+        let path = self.search_index_path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let ciphertext = self.encrypt_text(&index.format(), &self.recipients()?)?;
+        File::create(path)?.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    fn search_index_path(&self) -> PathBuf {
+        self.repo.path().join(SEARCH_INDEX_PATH)
+    }
+}
+
+/// Splits `text` into lowercase words, runs of letters and digits.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
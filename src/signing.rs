@@ -0,0 +1,199 @@
+//! Signing and verifying commits, configured with git's own settings: `commit.gpgSign`,
+//! `user.signingKey`, `gpg.format` and `gpg.ssh.allowedSignersFile`.
+
+use crate::cipher::gpg;
+use crate::{
+    find_executable, run_with_input, DearyError, Result, Verification, SIGNING_KEY_CONFIG,
+};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::NamedTempFile;
+
+const SSH_KEYGEN: &str = "ssh-keygen";
+const SSH_NAMESPACE: &str = "git";
+const SSH_SIGNATURE_HEADER: &str = "-----BEGIN SSH SIGNATURE-----";
+const SSH_KEY_PREFIX: &str = "key::";
+
+/// Signs commits either with gpg or with an SSH key, the same way `git commit -S` does, so
+/// that `git log --show-signature` can check them too.
+pub(crate) enum CommitSigner {
+    Gpg(String),
+    Ssh(String),
+}
+
+impl CommitSigner {
+    /// Reads the signer from the repository's git config. Returns `None` unless
+    /// `commit.gpgSign` is set. The key defaults to the one entries are signed with.
+    pub(crate) fn from_config(config: &git2::Config) -> Result<Option<CommitSigner>> {
+        if !config.get_bool("commit.gpgSign").unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let key = match get_string(config, "user.signingKey")? {
+            Some(key) => key,
+            None => match get_string(config, SIGNING_KEY_CONFIG)? {
+                Some(key) => key,
+                None => {
+                    return Err(DearyError::Config(
+                        "commit.gpgSign is set, but user.signingKey is not".to_string(),
+                    ))
+                }
+            },
+        };
+
+        match get_string(config, "gpg.format")?.as_deref() {
+            None | Some("openpgp") => Ok(Some(CommitSigner::Gpg(key))),
+            Some("ssh") => Ok(Some(CommitSigner::Ssh(key))),
+            Some(format) => Err(DearyError::Config(format!(
+                "Unsupported gpg.format {}",
+                format
+            ))),
+        }
+    }
+
+    /// Returns an armored detached signature of the commit `content`. Public keys given
+    /// literally are written to a temporary file in `tmp_dir`.
+    pub(crate) fn sign(&self, content: &str, tmp_dir: &Path) -> Result<String> {
+        match self {
+            CommitSigner::Gpg(key) => gpg::sign_detached(key, content.as_bytes()),
+            CommitSigner::Ssh(key) => {
+                // Like git, accept a literal public key as well as a path to a key file
+                let key_file = match key.strip_prefix(SSH_KEY_PREFIX) {
+                    Some(public_key) => Some(write_tmp_file(public_key.as_bytes(), tmp_dir)?),
+                    None if key.starts_with("ssh-") => {
+                        Some(write_tmp_file(key.as_bytes(), tmp_dir)?)
+                    }
+                    None => None,
+                };
+                let key_path = match &key_file {
+                    Some(file) => file.path().to_path_buf(),
+                    None => expand_home(key),
+                };
+
+                let output = run_with_input(
+                    Command::new(find_executable(SSH_KEYGEN)?)
+                        .args(["-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+                        .arg(key_path),
+                    content.as_bytes(),
+                )?;
+                if output.status.success() {
+                    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+                } else {
+                    Err(DearyError::Signing(stderr(&output)))
+                }
+            }
+        }
+    }
+}
+
+/// Tells whether `key` is meant for SSH signing: a public key, or a path to a key file.
+pub fn is_ssh_key(key: &str) -> bool {
+    key.starts_with(SSH_KEY_PREFIX) || key.starts_with("ssh-") || expand_home(key).is_file()
+}
+
+/// Verifies the detached `signature` of a commit's `content`. SSH signatures are only trusted if
+/// the signer is listed in `gpg.ssh.allowedSignersFile`.
+pub(crate) fn verify(
+    signature: &[u8],
+    content: &[u8],
+    config: &git2::Config,
+    tmp_dir: &Path,
+) -> Result<Verification> {
+    let signature_file = write_tmp_file(signature, tmp_dir)?;
+    if !signature.starts_with(SSH_SIGNATURE_HEADER.as_bytes()) {
+        return gpg::verify_detached(signature_file.path(), content);
+    }
+
+    let ssh_keygen = find_executable(SSH_KEYGEN)?;
+    let output = run_with_input(
+        Command::new(&ssh_keygen)
+            .args(["-Y", "check-novalidate", "-n", SSH_NAMESPACE, "-s"])
+            .arg(signature_file.path()),
+        content,
+    )?;
+    // "Good "git" signature with ED25519 key SHA256:..."
+    let key = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find(|w| w.starts_with("SHA256:"))
+        .unwrap_or("SSH key")
+        .to_string();
+    if !output.status.success() {
+        return Ok(Verification::Bad { signer: key });
+    }
+
+    let allowed_signers = match get_string(config, "gpg.ssh.allowedSignersFile")? {
+        Some(path) => expand_home(&path),
+        None => {
+            return Ok(Verification::Good {
+                signer: key,
+                trusted: false,
+            })
+        }
+    };
+    let principals = run_with_input(
+        Command::new(&ssh_keygen)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(signature_file.path()),
+        &[],
+    )?;
+    let principal = match String::from_utf8_lossy(&principals.stdout).lines().next() {
+        Some(p) if principals.status.success() => p.to_string(),
+        _ => {
+            return Ok(Verification::Good {
+                signer: key,
+                trusted: false,
+            })
+        }
+    };
+
+    let output = run_with_input(
+        Command::new(&ssh_keygen)
+            .args(["-Y", "verify", "-n", SSH_NAMESPACE, "-f"])
+            .arg(&allowed_signers)
+            .args(["-I", &principal, "-s"])
+            .arg(signature_file.path()),
+        content,
+    )?;
+    if output.status.success() {
+        Ok(Verification::Good {
+            signer: principal,
+            trusted: true,
+        })
+    } else {
+        Ok(Verification::Bad { signer: principal })
+    }
+}
+
+fn stderr(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if stderr.is_empty() {
+        format!("{} exited with {}", SSH_KEYGEN, output.status)
+    } else {
+        stderr
+    }
+}
+
+fn write_tmp_file(data: &[u8], tmp_dir: &Path) -> Result<NamedTempFile> {
+    let mut file = NamedTempFile::new_in(tmp_dir)?;
+    file.write_all(data)?;
+    file.flush()?;
+    Ok(file)
+}
+
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => Path::new(&std::env::var_os("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+fn get_string(config: &git2::Config, name: &str) -> Result<Option<String>> {
+    match config.get_string(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(DearyError::from(e)),
+    }
+}
@@ -0,0 +1,220 @@
+//! Synchronizing the diary with git remotes, e.g. between several devices.
+
+use crate::{Cipher, Deary, DearyError, Result};
+use git2::build::CheckoutBuilder;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+
+const DEFAULT_REMOTE: &str = "origin";
+const MAX_CREDENTIAL_ATTEMPTS: usize = 3;
+
+impl Deary {
+    /// Clones an existing diary from `url` into `repo_path`, to use it on another device.
+    pub fn clone_remote(
+        url: &str,
+        repo_path: &Path,
+        git_config: HashMap<&str, &str>,
+    ) -> Result<()> {
+        let config = git2::Config::open_default()?;
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(&config));
+        git2::build::RepoBuilder::new()
+            .fetch_options(fetch_options)
+            .clone(url, repo_path)?;
+
+        Deary::new(repo_path)?.set_config(git_config)
+    }
+}
+
+impl<C: Cipher> Deary<C> {
+    pub fn add_remote(&self, name: &str, url: &str) -> Result<()> {
+        self.repo.remote(name, url)?;
+        Ok(())
+    }
+
+    pub fn remove_remote(&self, name: &str) -> Result<()> {
+        self.repo.remote_delete(name)?;
+        Ok(())
+    }
+
+    /// Lists remotes as pairs of name and URL.
+    pub fn remotes(&self) -> Result<Vec<(String, String)>> {
+        let mut remotes = vec![];
+        for name in self.repo.remotes()?.iter().flatten() {
+            let remote = self.repo.find_remote(name)?;
+            remotes.push((
+                name.to_string(),
+                remote.url().unwrap_or_default().to_string(),
+            ));
+        }
+        Ok(remotes)
+    }
+
+    /// Pushes the current branch to `remote`, which defaults to the branch's upstream remote,
+    /// or `origin`. The first push makes `remote` the upstream of the branch. `force` overwrites
+    /// the remote branch even if it has commits that are not in the local one, e.g. after
+    /// [`Deary::purge_entry`].
+    pub fn push(&self, remote: Option<&str>, force: bool) -> Result<()> {
+        let branch = self.current_branch()?;
+        let remote_name = self.remote_name(remote)?;
+        let mut remote = self.repo.find_remote(&remote_name)?;
+        let config = self.repo.config()?;
+
+        let rejected = RefCell::new(None);
+        {
+            let mut callbacks = remote_callbacks(&config);
+            callbacks.push_update_reference(|name, status| {
+                if let Some(status) = status {
+                    *rejected.borrow_mut() = Some(format!("{} rejected: {}", name, status));
+                }
+                Ok(())
+            });
+            let mut push_options = git2::PushOptions::new();
+            push_options.remote_callbacks(callbacks);
+            let refspec = format!("{}{}:{}", if force { "+" } else { "" }, branch, branch);
+            match remote.push(&[refspec], Some(&mut push_options)) {
+                // Local remotes report rejected updates as errors instead
+                Err(e) if e.code() == git2::ErrorCode::NotFastForward => {
+                    *rejected.borrow_mut() = Some(format!("{} rejected", branch))
+                }
+                result => result?,
+            }
+        }
+        if let Some(message) = rejected.into_inner() {
+            return Err(DearyError::Sync(format!("{} (pull first)", message)));
+        }
+
+        let short_name = branch.trim_start_matches("refs/heads/");
+        let mut config = self.repo.config()?;
+        if config
+            .get_string(&format!("branch.{}.remote", short_name))
+            .is_err()
+        {
+            config.set_str(&format!("branch.{}.remote", short_name), &remote_name)?;
+            config.set_str(&format!("branch.{}.merge", short_name), &branch)?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the current branch from `remote` (see [`Deary::push`]) and fast-forwards to it,
+    /// or merges it if both sides have new commits.
+    pub fn pull(&self, remote: Option<&str>) -> Result<()> {
+        self.ensure_clean()?;
+        let branch = self.current_branch()?;
+        let short_name = branch.trim_start_matches("refs/heads/");
+        let remote_name = self.remote_name(remote)?;
+        let mut remote = self.repo.find_remote(&remote_name)?;
+        let config = self.repo.config()?;
+        let merge_ref = config
+            .get_string(&format!("branch.{}.merge", short_name))
+            .unwrap_or_else(|_| branch.clone());
+
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(&config));
+        let refspec = format!(
+            "{}:refs/remotes/{}/{}",
+            merge_ref,
+            remote_name,
+            merge_ref.trim_start_matches("refs/heads/")
+        );
+        remote.fetch(&[refspec], Some(&mut fetch_options), None)?;
+
+        let fetch_head = match self.repo.find_reference("FETCH_HEAD") {
+            Ok(r) => r,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {
+                return Err(DearyError::Sync(format!(
+                    "{} not found on {}",
+                    merge_ref, remote_name
+                )))
+            }
+            Err(e) => return Err(DearyError::from(e)),
+        };
+        let theirs = self.repo.reference_to_annotated_commit(&fetch_head)?;
+        let (analysis, _) = self.repo.merge_analysis(&[&theirs])?;
+
+        if analysis.is_up_to_date() {
+            Ok(())
+        } else if analysis.is_fast_forward() || analysis.is_unborn() {
+            self.repo
+                .reference(&branch, theirs.id(), true, "pull: fast-forward")?;
+            self.repo
+                .checkout_head(Some(CheckoutBuilder::new().force()))?;
+            Ok(())
+        } else {
+            let theirs = self.repo.find_commit(theirs.id())?;
+            self.merge(&theirs, &format!("Merge {}", remote_name))
+        }
+    }
+
+    fn merge(&self, theirs: &git2::Commit, message: &str) -> Result<()> {
+        let ours = self.repo.head()?.peel_to_commit()?;
+        let mut index = self.repo.merge_commits(&ours, theirs, None)?;
+        if index.has_conflicts() {
+            self.resolve_conflicts(&mut index)?;
+        }
+
+        let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+        self.commit_tree(&tree, &[&ours, theirs], message)?;
+        self.repo
+            .checkout_head(Some(CheckoutBuilder::new().force()))?;
+        Ok(())
+    }
+
+    /// The full name of the branch HEAD points to, e.g. `refs/heads/master`.
+    fn current_branch(&self) -> Result<String> {
+        match self.repo.find_reference("HEAD")?.symbolic_target() {
+            Some(branch) => Ok(branch.to_string()),
+            None => Err(DearyError::Sync("HEAD is detached".to_string())),
+        }
+    }
+
+    fn remote_name(&self, remote: Option<&str>) -> Result<String> {
+        if let Some(remote) = remote {
+            return Ok(remote.to_string());
+        }
+        let branch = self.current_branch()?;
+        let key = format!("branch.{}.remote", branch.trim_start_matches("refs/heads/"));
+        Ok(self
+            .repo
+            .config()?
+            .get_string(&key)
+            .unwrap_or_else(|_| DEFAULT_REMOTE.to_string()))
+    }
+
+    /// Refuses to touch the working tree if it has changes deary did not commit.
+    fn ensure_clean(&self) -> Result<()> {
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true);
+        let statuses = self.repo.statuses(Some(&mut options))?;
+        if statuses.iter().any(|s| s.status() != git2::Status::IGNORED) {
+            return Err(DearyError::Sync(format!(
+                "{} has uncommitted changes",
+                self.repo_dir().display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Authenticates with the SSH agent, or with git's credential helpers for HTTPS remotes.
+fn remote_callbacks(config: &git2::Config) -> git2::RemoteCallbacks<'_> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    let attempts = Cell::new(0);
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking for as long as authentication fails
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("Authentication failed"));
+        }
+
+        if allowed.contains(git2::CredentialType::SSH_KEY) {
+            git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+            git2::Cred::credential_helper(config, url, username)
+        } else {
+            git2::Cred::default()
+        }
+    });
+    callbacks
+}
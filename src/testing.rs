@@ -0,0 +1,95 @@
+//! Helpers for tests: a cipher that does not need any keys, and diaries using it.
+
+use crate::{Cipher, Config, Deary, DearyError, Result, GPG_ID_FILE_NAME};
+use std::collections::HashMap;
+use std::path::Path;
+
+const MOCK_HEADER: &str = "mock";
+
+/// A cipher that only prepends the recipients, and a random nonce to make it as
+/// non-deterministic as real encryption, to the plaintext.
+pub(crate) struct MockCipher;
+
+impl Cipher for MockCipher {
+    fn encrypt(&self, plaintext: &[u8], recipients: &[String]) -> Result<Vec<u8>> {
+        let header = format!(
+            "{}:{}:{}\n",
+            MOCK_HEADER,
+            recipients.join(","),
+            rand::random::<u64>()
+        );
+        Ok([header.as_bytes(), plaintext].concat())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        match ciphertext.iter().position(|b| *b == b'\n') {
+            Some(end) if ciphertext.starts_with(MOCK_HEADER.as_bytes()) => {
+                Ok(ciphertext[end + 1..].to_vec())
+            }
+            _ => Err(DearyError::Decryption("Not a mock ciphertext".to_string())),
+        }
+    }
+}
+
+/// The recipients `ciphertext` was encrypted to by [`MockCipher`].
+pub(crate) fn mock_recipients(ciphertext: &[u8]) -> Vec<String> {
+    let header = String::from_utf8_lossy(ciphertext);
+    let recipients = header.lines().next().unwrap().split(':').nth(1).unwrap();
+    recipients.split(',').map(String::from).collect()
+}
+
+/// Creates a diary in `dir`, encrypted to `recipients` with [`MockCipher`], ignoring the
+/// user's settings.
+pub(crate) fn mock_diary(dir: &Path, recipients: &[&str]) -> Deary<MockCipher> {
+    git2::Repository::init(dir).unwrap();
+    let deary = open_mock_diary(dir);
+    deary
+        .create_recipients_file(GPG_ID_FILE_NAME, recipients)
+        .unwrap();
+    deary
+}
+
+/// Clones the diary in `origin` into `dir`, like [`mock_diary`].
+pub(crate) fn mock_clone(origin: &Path, dir: &Path) -> Deary<MockCipher> {
+    git2::Repository::clone(&origin.to_string_lossy(), dir).unwrap();
+    open_mock_diary(dir)
+}
+
+fn open_mock_diary(dir: &Path) -> Deary<MockCipher> {
+    let mut deary = Deary::with_cipher(dir, MockCipher).unwrap();
+    deary.config = Config {
+        editor: Some("sh".to_string()),
+        // Entries created in the same second need different names
+        entry_name_format: Some("%Y%m%d-%H%M%S%.9f".to_string()),
+        ..Config::default()
+    };
+    let mut git_config = HashMap::new();
+    git_config.insert("user.name", "test");
+    git_config.insert("user.email", "test@example.com");
+    git_config.insert("commit.gpgSign", "false");
+    deary.set_config(git_config).unwrap();
+    deary
+}
+
+/// Makes the editor replace the text of the file it opens with `text`.
+pub(crate) fn mock_editor(deary: &mut Deary<MockCipher>, text: &str) {
+    // The editor runs as `sh -c <script> <text> <file>`
+    deary.config.editor_args = vec![
+        "-c".to_string(),
+        "printf '%s' \"$0\" > \"$1\"".to_string(),
+        text.to_string(),
+    ];
+}
+
+/// Creates an entry with `text`, returning its name.
+pub(crate) fn mock_entry(deary: &mut Deary<MockCipher>, text: &str) -> String {
+    mock_editor(deary, text);
+    let names = deary.list_entries().unwrap();
+    deary.create_entry().unwrap();
+    deary
+        .list_entries()
+        .unwrap()
+        .into_iter()
+        .find(|n| !names.contains(n))
+        .unwrap()
+}
@@ -0,0 +1,128 @@
+//! Deleted entries, which remain in the history and can be brought back from there.
+
+use crate::history::{blob_id, commit_time, revision};
+use crate::metadata::parse_metadata_index;
+use crate::{
+    parse_index, Change, Cipher, Deary, DearyError, EntryInfo, Metadata, Result, Revision,
+    INDEX_FILE_NAME, METADATA_FILE_NAME,
+};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::prelude::*;
+
+/// An entry that was deleted, see [`Deary::deleted_entries`].
+pub struct DeletedEntry {
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    /// The commit that deleted the entry.
+    pub deleted_in: Revision,
+    pub(crate) file_name: String,
+    /// The last commit that still had the entry.
+    last_commit: git2::Oid,
+}
+
+impl<C: Cipher> Deary<C> {
+    /// Lists entries deleted in the history that have not been brought back, most recently
+    /// deleted first.
+    pub fn deleted_entries(&self) -> Result<Vec<DeletedEntry>> {
+        let live = self.list_entries()?;
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_head()?;
+
+        let mut deleted: Vec<DeletedEntry> = vec![];
+        for oid in revwalk {
+            let commit = self.repo.find_commit(oid?)?;
+            let parent = match commit.parents().next() {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let diff =
+                self.repo
+                    .diff_tree_to_tree(Some(&parent.tree()?), Some(&commit.tree()?), None)?;
+
+            let deleted_files: Vec<String> = diff
+                .deltas()
+                .filter(|d| d.status() == git2::Delta::Deleted)
+                .filter_map(|d| d.old_file().path().and_then(|p| p.to_str()))
+                .filter(|f| !f.starts_with('.'))
+                .map(String::from)
+                .collect();
+            if deleted_files.is_empty() {
+                continue;
+            }
+
+            // In a private diary, only the index knows which entry a file was
+            let parent_index = match blob_id(&parent, INDEX_FILE_NAME) {
+                Some(id) => Some(parse_index(
+                    &self.cipher.decrypt(self.repo.find_blob(id)?.content())?,
+                )),
+                None => None,
+            };
+            for file_name in deleted_files {
+                let name = match &parent_index {
+                    Some(index) => match index.iter().find(|(_, f)| *f == file_name) {
+                        Some((name, _)) => name.clone(),
+                        None => continue,
+                    },
+                    None => file_name.clone(),
+                };
+
+                // Entries renamed by `make_private`, or brought back since, are not deleted
+                if live.contains(&name) || deleted.iter().any(|d| d.name == name) {
+                    continue;
+                }
+                deleted.push(DeletedEntry {
+                    name,
+                    deleted_at: commit_time(&commit),
+                    deleted_in: revision(&commit),
+                    file_name,
+                    last_commit: parent.id(),
+                });
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// The metadata a deleted entry had right before it was deleted, from the metadata index of
+    /// the last commit that had it. Entries missing from that index have none.
+    pub fn deleted_entry_info(&self, entry: &DeletedEntry) -> Result<EntryInfo> {
+        let commit = self.repo.find_commit(entry.last_commit)?;
+        let index = match blob_id(&commit, METADATA_FILE_NAME) {
+            Some(id) => {
+                parse_metadata_index(&self.cipher.decrypt(self.repo.find_blob(id)?.content())?)
+            }
+            None => vec![],
+        };
+        Ok(match index.into_iter().find(|e| e.name == entry.name) {
+            Some(info) => info,
+            None => EntryInfo {
+                name: entry.name.clone(),
+                created: self.created_from_name(&entry.name),
+                size: None,
+                metadata: Metadata::default(),
+            },
+        })
+    }
+
+    /// Brings back a deleted entry as it was right before it was deleted.
+    pub fn undelete_entry(&self, name: &str) -> Result<()> {
+        let entry = match self.deleted_entries()?.into_iter().find(|d| d.name == name) {
+            Some(entry) => entry,
+            None => return Err(DearyError::EntryNotFound(name.to_string())),
+        };
+        let commit = self.repo.find_commit(entry.last_commit)?;
+        let id = blob_id(&commit, &entry.file_name).unwrap();
+        let text = self.cipher.decrypt(self.repo.find_blob(id)?.content())?;
+
+        // Re-encrypt, as the recipients may have changed since
+        let ciphertext = self.encrypt_text(&text, &self.recipients()?)?;
+        File::create(self.repo_dir().join(&entry.file_name))?.write_all(&ciphertext)?;
+        self.update_metadata(&entry.name, &text)?;
+        if self.is_private() {
+            let mut index = self.read_index()?;
+            index.push((entry.name, entry.file_name.clone()));
+            self.write_index(&index)?;
+        }
+        self.commit_entry(&entry.file_name, Change::Add)
+    }
+}